@@ -0,0 +1,39 @@
+//! Decode throughput, `parse` plus `into_rgba8`, over a few representative
+//! files. Run with `cargo bench`.
+
+use std::time::{Duration, Instant};
+
+use ruro::png::{PNGFile, PNGParseError};
+
+const FIXTURES: [(&str, &str); 3] = [
+    ("small paletted", "benches/fixtures/paletted.png"),
+    ("large truecolor", "image.png"),
+    ("interlaced", "benches/fixtures/interlaced.png"),
+];
+
+/// How long each fixture is decoded over and over.
+const MEASURE_TIME: Duration = Duration::from_secs(2);
+
+fn decode(bytes: &[u8]) -> Result<Vec<u8>, PNGParseError> {
+    PNGFile::from_bytes(bytes)?.into_rgba8()
+}
+
+fn main() {
+    for (name, path) in FIXTURES {
+        let path = format!("{}/{}", env!("CARGO_MANIFEST_DIR"), path);
+        let bytes = std::fs::read(&path).unwrap_or_else(|e| panic!("Could not read `{}`: {}", path, e));
+        let output_len = decode(&bytes).expect("fixture should decode").len();
+        let mut iterations: u32 = 0;
+        let start = Instant::now();
+        while start.elapsed() < MEASURE_TIME {
+            std::hint::black_box(decode(std::hint::black_box(&bytes)).unwrap());
+            iterations += 1;
+        }
+        let per_decode = start.elapsed() / iterations;
+        let throughput = output_len as f64 / per_decode.as_secs_f64() / 1_000_000.0;
+        println!(
+            "{:<16} {:>10.3?}/decode {:>8.1} MB/s of RGBA8 output",
+            name, per_decode, throughput
+        );
+    }
+}
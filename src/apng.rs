@@ -0,0 +1,202 @@
+//! APNG animations, see `PNGFile::animation`.
+
+use crate::png::{PNGFile, PNGParseError};
+
+/// What happens to the frame area before the next frame is rendered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisposeOp {
+    /// Leave the canvas as it is.
+    None,
+    /// Clear the frame area to transparent black.
+    Background,
+    /// Restore the frame area to what it was before the frame was rendered.
+    Previous,
+}
+
+/// How the frame is combined with the canvas.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlendOp {
+    /// Overwrite the frame area, alpha included.
+    Source,
+    /// Alpha composite the frame over the canvas.
+    Over,
+}
+
+/// A frame of an animation, described by an fcTL chunk.
+#[derive(Clone)]
+pub struct Frame {
+    pub width: u32,
+    pub height: u32,
+    pub x_offset: u32,
+    pub y_offset: u32,
+    /// Numerator of the frame delay in seconds.
+    pub delay_num: u16,
+    /// Denominator of the frame delay, 0 means 100.
+    pub delay_den: u16,
+    pub dispose_op: DisposeOp,
+    pub blend_op: BlendOp,
+    /// Decoded frame pixels as row-major RGBA8, `width * height * 4` bytes.
+    pub pixels: Vec<u8>,
+}
+
+impl Frame {
+    /// Decode an fcTL chunk, checking the frame fits on the canvas.
+    pub(crate) fn from_frame_control(
+        data: &[u8],
+        canvas_width: u32,
+        canvas_height: u32,
+    ) -> Result<Frame, PNGParseError> {
+        if data.len() != 26 {
+            return Err(PNGParseError::ParseError("Invalid fcTL chunk."));
+        }
+        let u32_at = |i: usize| u32::from_be_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+        let u16_at = |i: usize| u16::from_be_bytes([data[i], data[i + 1]]);
+        let dispose_op = match data[24] {
+            0 => DisposeOp::None,
+            1 => DisposeOp::Background,
+            2 => DisposeOp::Previous,
+            _ => return Err(PNGParseError::ParseError("Invalid fcTL dispose op.")),
+        };
+        let blend_op = match data[25] {
+            0 => BlendOp::Source,
+            1 => BlendOp::Over,
+            _ => return Err(PNGParseError::ParseError("Invalid fcTL blend op.")),
+        };
+        // the sequence number in the first four bytes is not needed
+        let frame = Frame {
+            width: u32_at(4),
+            height: u32_at(8),
+            x_offset: u32_at(12),
+            y_offset: u32_at(16),
+            delay_num: u16_at(20),
+            delay_den: u16_at(22),
+            dispose_op,
+            blend_op,
+            pixels: vec![],
+        };
+        let fits = |offset: u32, len: u32, max: u32| len > 0 && offset as u64 + len as u64 <= max as u64;
+        if !fits(frame.x_offset, frame.width, canvas_width) || !fits(frame.y_offset, frame.height, canvas_height) {
+            return Err(PNGParseError::ParseError("fcTL frame is outside the image."));
+        }
+        Ok(frame)
+    }
+
+    /// Frame delay in seconds.
+    pub fn delay(&self) -> f64 {
+        let den = if self.delay_den == 0 { 100 } else { self.delay_den };
+        self.delay_num as f64 / den as f64
+    }
+}
+
+/// Animation data of an APNG file, from its acTL, fcTL and fdAT chunks.
+#[derive(Clone)]
+pub struct Apng {
+    /// Canvas size, taken from IHDR.
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    pub(crate) num_frames: u32,
+    pub(crate) num_plays: u32,
+    pub(crate) frames: Vec<Frame>,
+    /// Whether an fcTL came before IDAT, making the default image the first frame.
+    pub(crate) default_image_is_first_frame: bool,
+}
+
+impl Apng {
+    /// Decode an acTL chunk for an animation on a `width` x `height` canvas.
+    pub(crate) fn from_animation_control(data: &[u8], width: u32, height: u32) -> Result<Apng, PNGParseError> {
+        if data.len() != 8 {
+            return Err(PNGParseError::ParseError("Invalid acTL chunk."));
+        }
+        Ok(Apng {
+            width,
+            height,
+            num_frames: u32::from_be_bytes([data[0], data[1], data[2], data[3]]),
+            num_plays: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+            frames: vec![],
+            default_image_is_first_frame: false,
+        })
+    }
+
+    /// Number of frames from acTL. Parsing checks it matches the fcTL
+    /// chunks, so it's also the length of `frames`.
+    pub fn num_frames(&self) -> u32 {
+        self.num_frames
+    }
+
+    /// Whether the default image (IDAT) is the first frame, i.e. an fcTL came
+    /// before it. Otherwise the default image is only shown by decoders
+    /// without APNG support and the frames all come from fdAT chunks.
+    pub fn default_image_is_first_frame(&self) -> bool {
+        self.default_image_is_first_frame
+    }
+
+    /// Number of times to play the animation, 0 means forever.
+    pub fn num_plays(&self) -> u32 {
+        self.num_plays
+    }
+
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    /// Render frame `index` as a full RGBA8 image, compositing it over the
+    /// previous frames according to their blend and dispose ops.
+    pub fn render_frame(&self, index: usize) -> Result<PNGFile, PNGParseError> {
+        if index >= self.frames.len() {
+            return Err(PNGParseError::ParseError("Frame index out of range."));
+        }
+        let mut canvas = vec![0u8; self.width as usize * self.height as usize * 4];
+        for frame in &self.frames[..index] {
+            let previous = (frame.dispose_op == DisposeOp::Previous).then(|| canvas.clone());
+            self.blend(&mut canvas, frame);
+            match frame.dispose_op {
+                DisposeOp::None => {}
+                DisposeOp::Background => self.clear(&mut canvas, frame),
+                DisposeOp::Previous => canvas = previous.unwrap_or_default(),
+            }
+        }
+        self.blend(&mut canvas, &self.frames[index]);
+        Ok(PNGFile::with_rgba8(self.width, self.height, canvas))
+    }
+
+    /// Byte offset of canvas pixel (x, y).
+    fn offset(&self, x: u32, y: u32) -> usize {
+        (y as usize * self.width as usize + x as usize) * 4
+    }
+
+    fn blend(&self, canvas: &mut [u8], frame: &Frame) {
+        for (row, line) in frame.pixels.chunks_exact(frame.width as usize * 4).enumerate() {
+            for (col, src) in line.chunks_exact(4).enumerate() {
+                let at = self.offset(frame.x_offset + col as u32, frame.y_offset + row as u32);
+                let dst = &mut canvas[at..at + 4];
+                match frame.blend_op {
+                    BlendOp::Source => dst.copy_from_slice(src),
+                    BlendOp::Over => over(dst, src),
+                }
+            }
+        }
+    }
+
+    /// Clear the frame area to transparent black.
+    fn clear(&self, canvas: &mut [u8], frame: &Frame) {
+        for row in 0..frame.height {
+            let start = self.offset(frame.x_offset, frame.y_offset + row);
+            canvas[start..start + frame.width as usize * 4].fill(0);
+        }
+    }
+}
+
+/// Alpha composite the straight alpha pixel `src` over `dst`.
+fn over(dst: &mut [u8], src: &[u8]) {
+    let src_a = src[3] as u32;
+    let dst_a = dst[3] as u32 * (255 - src_a) / 255;
+    let out_a = src_a + dst_a;
+    if out_a == 0 {
+        dst.fill(0);
+        return;
+    }
+    for c in 0..3 {
+        dst[c] = ((src[c] as u32 * src_a + dst[c] as u32 * dst_a) / out_a) as u8;
+    }
+    dst[3] = out_a as u8;
+}
@@ -0,0 +1,4 @@
+pub mod apng;
+pub mod png;
+mod transform;
+pub mod view;
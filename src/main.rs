@@ -1,36 +1,271 @@
+use std::io::Write;
 use std::path::Path;
 
-use clap::Parser;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use ruro::png;
 
-pub mod png;
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Format {
+    Text,
+    Json,
+}
 
 #[derive(Debug, Parser)]
-#[command(version, about, long_about=None)]
-struct Args {
+#[command(version, about, long_about=None, args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Arguments of `inspect`, which runs when no subcommand is given.
+    #[command(flatten)]
+    inspect: InspectArgs,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Print the image properties and chunks (the default).
+    Inspect(InspectArgs),
+    /// Write the decoded pixels, as raw 8-bit RGBA rows by default.
+    Decode {
+        #[arg(short, long)]
+        input: String,
+        #[arg(short, long, default_value_t=String::from("output.rgba"))]
+        output: String,
+        /// Layout of the output file.
+        #[arg(short, long, value_enum, default_value_t=PixelFormat::Raw)]
+        format: PixelFormat,
+    },
+    /// Re-encode the image as PNG, optionally changing its color type.
+    Convert {
+        #[arg(short, long)]
+        input: String,
+        #[arg(short, long, default_value_t=String::from("output.png"))]
+        output: String,
+        /// Color type to convert to, keeps the original one if not given.
+        #[arg(short, long, value_enum)]
+        color_type: Option<TargetColor>,
+        /// Bit depth to convert to along with the color type.
+        #[arg(short, long, default_value_t = 8)]
+        bit_depth: u8,
+    },
+}
+
+#[derive(Debug, Args)]
+struct InspectArgs {
     #[arg(short, long)]
-    input: String,
-    #[arg(short, long, default_value_t=String::from("output.png"))]
-    output: String,
+    input: Option<String>,
+    /// Accepted and ignored so older invocations keep working.
+    #[arg(short, long, hide = true)]
+    output: Option<String>,
+    /// How to print the file description.
+    #[arg(short, long, value_enum, default_value_t=Format::Text)]
+    format: Format,
+    /// Only read the header and print the image properties.
+    #[arg(long)]
+    info: bool,
 }
 
-fn main() {
-    let args = Args::parse();
-    let input_file_path = args.input;
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum PixelFormat {
+    /// Raw 8-bit RGBA samples without a header.
+    Raw,
+    /// Binary PPM, or PAM if the image has transparency.
+    Ppm,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum TargetColor {
+    Gray,
+    GrayAlpha,
+    Rgb,
+    Rgba,
+}
+
+impl From<TargetColor> for png::ColorType {
+    fn from(color: TargetColor) -> png::ColorType {
+        match color {
+            TargetColor::Gray => png::ColorType::Grayscale,
+            TargetColor::GrayAlpha => png::ColorType::GrayscaleAlpha,
+            TargetColor::Rgb => png::ColorType::Rgb,
+            TargetColor::Rgba => png::ColorType::Rgba,
+        }
+    }
+}
+
+/// Quote `s` as a JSON string.
+fn json_string(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Describe the parsed file and every chunk in it as a JSON object. Prints
+/// nothing if reading the chunks fails.
+fn print_json(image_file: &png::PNGFile, input_file_path: &str) -> Result<(), png::PNGParseError> {
+    let mut chunks: Vec<String> = vec![];
+    for header in png::PNGFile::iter_chunks(std::fs::File::open(input_file_path)?) {
+        let header = header?;
+        chunks.push(format!(
+            "{{\"type\":{},\"size\":{},\"crc_ok\":{}}}",
+            json_string(&header.type_str()),
+            header.size,
+            header.crc_ok
+        ));
+    }
+    println!(
+        "{{\"width\":{},\"height\":{},\"bit_depth\":{},\"color_type\":{},\"interlaced\":{},\"chunks\":[{}]}}",
+        image_file.width(),
+        image_file.height(),
+        image_file.bit_depth(),
+        image_file.color_type() as u8,
+        image_file.is_interlaced(),
+        chunks.join(",")
+    );
+    Ok(())
+}
+
+/// The error followed by the one it wraps for errors located in a chunk,
+/// e.g. "in `IDAT` chunk at byte 33: Parse error: `Invalid CRC`.".
+fn describe(e: &png::PNGParseError) -> String {
+    match e {
+        png::PNGParseError::InChunk { source, .. } => format!("{}: {}", e, describe(source)),
+        e => e.to_string(),
+    }
+}
+
+/// Exit unless `input_file_path` looks like a PNG file.
+fn check_extension(input_file_path: &str) {
     let file_segs: Vec<&str> = input_file_path.split(".").collect();
     let file_ext = if file_segs.len() >= 2 {
-        file_segs.get(file_segs.len() - 1).unwrap()
+        file_segs.last().unwrap()
     } else {
         ""
     };
 
-    // verify
-    if Path::new(&input_file_path).exists() {
-        if file_ext != "png" {
-            println!("Unrecognized file format, supported formats are: (png).");
+    if Path::new(input_file_path).exists() && file_ext != "png" {
+        println!("Unrecognized file format, supported formats are: (png).");
+        std::process::exit(1);
+    }
+}
+
+/// Open and parse `input_file_path`, exiting with a message on failure.
+fn open_or_exit(input_file_path: &str) -> png::PNGFile {
+    check_extension(input_file_path);
+    match png::PNGFile::open(input_file_path) {
+        Ok(image_file) => image_file,
+        Err(png::PNGParseError::Io(e)) => {
+            println!("Could not open `{}`: {}", input_file_path, e);
             std::process::exit(1);
         }
+        Err(e) => {
+            println!("Could not parse `{}`: {}", input_file_path, describe(&e));
+            std::process::exit(1);
+        }
+    }
+}
+
+fn inspect(args: InspectArgs) {
+    let Some(input_file_path) = args.input else {
+        println!("No input file, pass one with `--input`.");
+        std::process::exit(2);
+    };
+
+    if args.info {
+        check_extension(&input_file_path);
+        let header = std::fs::File::open(&input_file_path)
+            .map_err(png::PNGParseError::from)
+            .and_then(png::PNGFile::read_info);
+        match header {
+            Ok(header) => println!(
+                "width:{} height:{} bit_depth:{} color_type:{} interlaced:{}",
+                header.width(),
+                header.height(),
+                header.bit_depth(),
+                header.color_type_name(),
+                header.is_interlaced()
+            ),
+            Err(e) => {
+                println!("Could not parse `{}`: {}", input_file_path, describe(&e));
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let image_file = open_or_exit(&input_file_path);
+
+    if let Format::Json = args.format {
+        if let Err(e) = print_json(&image_file, &input_file_path) {
+            println!("Could not parse `{}`: {}", input_file_path, describe(&e));
+            std::process::exit(1);
+        }
+        return;
     }
 
-    let mut image_file = png::PNGFile::init(input_file_path);
-    let _ = image_file.parse();
+    println!("{}", image_file);
+    println!("chunks length: {}", image_file.chunks().len());
+    for (i, chunk) in image_file.chunks().iter().enumerate() {
+        println!(
+            "chunk:\t index: {} size:{} type:{}",
+            i,
+            chunk.size(),
+            chunk.type_str()
+        );
+    }
+}
+
+fn decode(input_file_path: &str, output_file_path: &str, format: PixelFormat) {
+    let image_file = open_or_exit(input_file_path);
+    let written = match format {
+        PixelFormat::Raw => image_file
+            .into_rgba8()
+            .and_then(|pixels| std::fs::write(output_file_path, pixels).map_err(png::PNGParseError::from)),
+        PixelFormat::Ppm => std::fs::File::create(output_file_path)
+            .map_err(png::PNGParseError::from)
+            .and_then(|file| {
+                let mut writer = std::io::BufWriter::new(file);
+                image_file.write_ppm(&mut writer)?;
+                writer.flush()?;
+                Ok(())
+            }),
+    };
+    if let Err(e) = written {
+        println!("Could not decode `{}`: {}", input_file_path, describe(&e));
+        std::process::exit(1);
+    }
+}
+
+fn convert(input_file_path: &str, output_file_path: &str, color_type: Option<TargetColor>, bit_depth: u8) {
+    let image_file = open_or_exit(input_file_path);
+    let written = match color_type {
+        Some(color_type) => image_file.convert(color_type.into(), bit_depth),
+        None => Ok(image_file),
+    }
+    .and_then(|converted| converted.save(output_file_path));
+    if let Err(e) = written {
+        println!("Could not convert `{}`: {}", input_file_path, describe(&e));
+        std::process::exit(1);
+    }
+}
+
+fn main() {
+    let cli = Cli::parse();
+    match cli.command {
+        None => inspect(cli.inspect),
+        Some(Command::Inspect(args)) => inspect(args),
+        Some(Command::Decode { input, output, format }) => decode(&input, &output, format),
+        Some(Command::Convert {
+            input,
+            output,
+            color_type,
+            bit_depth,
+        }) => convert(&input, &output, color_type, bit_depth),
+    }
 }
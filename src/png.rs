@@ -1,10 +1,19 @@
-use std::collections::HashMap;
+//! PNG decoding and encoding.
+//!
+//! Properties of a decoded `PNGFile` or `Chunk` are read through getter
+//! methods named after the property (`width()`, `palette()`, ...), the
+//! structs themselves keep their fields private. Plain value types such as
+//! `Rgb` and `TextEntry` expose their fields directly.
+
+use std::borrow::Cow;
 use std::io::prelude::*;
-use std::sync::Once;
-use std::usize;
-use std::{fs::File};
+use std::fs::File;
+use std::path::Path;
 use thiserror::Error;
-use flate2::read::{ZlibDecoder};
+
+use crate::apng::{Apng, Frame};
+use flate2::write::ZlibEncoder;
+use flate2::{Compression, Decompress, FlushDecompress, Status};
 
 #[derive(Debug, Error)]
 pub enum PNGParseError {
@@ -14,222 +23,4118 @@ pub enum PNGParseError {
     ParseError(&'static str),
     #[error("End of file error.")]
     EOF,
+    #[error("Unknown filter type `{0}`.")]
+    UnknownFilter(u8),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    /// An error from a specific chunk, with the chunk type and the byte
+    /// offset of the chunk's start in the stream. Only the location is
+    /// displayed, the error itself is the `source`.
+    #[error("in `{chunk_type}` chunk at byte {offset}")]
+    InChunk {
+        chunk_type: String,
+        offset: u64,
+        #[source]
+        source: Box<PNGParseError>,
+    },
+}
+
+/// I/O errors can't be cloned, a clone keeps their kind and message.
+impl Clone for PNGParseError {
+    fn clone(&self) -> Self {
+        match self {
+            PNGParseError::InvalidFile(message) => PNGParseError::InvalidFile(message),
+            PNGParseError::ParseError(message) => PNGParseError::ParseError(message),
+            PNGParseError::EOF => PNGParseError::EOF,
+            PNGParseError::UnknownFilter(filter_type) => PNGParseError::UnknownFilter(*filter_type),
+            PNGParseError::Io(e) => PNGParseError::Io(std::io::Error::new(e.kind(), e.to_string())),
+            PNGParseError::InChunk {
+                chunk_type,
+                offset,
+                source,
+            } => PNGParseError::InChunk {
+                chunk_type: chunk_type.clone(),
+                offset: *offset,
+                source: source.clone(),
+            },
+        }
+    }
+}
+
+impl PNGParseError {
+    /// Attach the chunk an error came from.
+    fn in_chunk(self, chunk_type: &[u8; 4], offset: u64) -> PNGParseError {
+        PNGParseError::InChunk {
+            chunk_type: String::from_utf8_lossy(chunk_type).into_owned(),
+            offset,
+            source: Box::new(self),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rgb16 {
+    pub r: u16,
+    pub g: u16,
+    pub b: u16,
+}
+
+/// Color type from IHDR, the discriminants are the values stored in the file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[repr(u8)]
+pub enum ColorType {
+    #[default]
+    Grayscale = 0,
+    Rgb = 2,
+    Palette = 3,
+    GrayscaleAlpha = 4,
+    Rgba = 6,
+}
+
+impl TryFrom<u8> for ColorType {
+    type Error = PNGParseError;
+
+    fn try_from(value: u8) -> Result<ColorType, PNGParseError> {
+        match value {
+            0 => Ok(ColorType::Grayscale),
+            2 => Ok(ColorType::Rgb),
+            3 => Ok(ColorType::Palette),
+            4 => Ok(ColorType::GrayscaleAlpha),
+            6 => Ok(ColorType::Rgba),
+            _ => Err(PNGParseError::ParseError("Invalid color type.")),
+        }
+    }
+}
+
+impl ColorType {
+    /// Number of samples per pixel.
+    pub fn channels(self) -> u8 {
+        match self {
+            ColorType::Grayscale | ColorType::Palette => 1,
+            ColorType::GrayscaleAlpha => 2,
+            ColorType::Rgb => 3,
+            ColorType::Rgba => 4,
+        }
+    }
+
+    /// Human readable name, e.g. `RGBA`.
+    pub fn name(self) -> &'static str {
+        match self {
+            ColorType::Grayscale => "grayscale",
+            ColorType::Rgb => "RGB",
+            ColorType::Palette => "indexed",
+            ColorType::GrayscaleAlpha => "grayscale+alpha",
+            ColorType::Rgba => "RGBA",
+        }
+    }
+}
+
+/// Transparency information from the tRNS chunk.
+#[derive(Clone, Debug)]
+pub enum Transparency {
+    /// Alpha of each palette entry, entries past the end are fully opaque.
+    Palette(Vec<u8>),
+    /// Grayscale sample value that is fully transparent.
+    Gray(u16),
+    /// RGB sample values of the fully transparent color.
+    Rgb(u16, u16, u16),
+}
+
+/// Rendering intent from the sRGB chunk.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RenderingIntent {
+    Perceptual,
+    RelativeColorimetric,
+    Saturation,
+    AbsoluteColorimetric,
 }
 
-struct RGB {
-    r: u8,
-    g: u8,
-    b: u8,
+/// CIE 1931 chromaticities from the cHRM chunk, stored as in the file,
+/// scaled by 100000.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Chromaticities {
+    pub white_x: u32,
+    pub white_y: u32,
+    pub red_x: u32,
+    pub red_y: u32,
+    pub green_x: u32,
+    pub green_y: u32,
+    pub blue_x: u32,
+    pub blue_y: u32,
 }
 
-struct Chunk {
+impl Chromaticities {
+    fn scaled(x: u32, y: u32) -> (f64, f64) {
+        (x as f64 / 100000.0, y as f64 / 100000.0)
+    }
+
+    /// White point as (x, y).
+    pub fn white_point(&self) -> (f64, f64) {
+        Chromaticities::scaled(self.white_x, self.white_y)
+    }
+
+    /// Red primary as (x, y).
+    pub fn red(&self) -> (f64, f64) {
+        Chromaticities::scaled(self.red_x, self.red_y)
+    }
+
+    /// Green primary as (x, y).
+    pub fn green(&self) -> (f64, f64) {
+        Chromaticities::scaled(self.green_x, self.green_y)
+    }
+
+    /// Blue primary as (x, y).
+    pub fn blue(&self) -> (f64, f64) {
+        Chromaticities::scaled(self.blue_x, self.blue_y)
+    }
+}
+
+/// Time of the last image modification from the tIME chunk, in UTC.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimeStamp {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    /// Up to 60 to allow for leap seconds.
+    pub second: u8,
+}
+
+/// A suggested palette from an sPLT chunk.
+#[derive(Clone, Debug)]
+pub struct SuggestedPalette {
+    pub name: String,
+    /// Bits per sample of the entries as stored, 8 or 16.
+    pub sample_depth: u8,
+    pub entries: Vec<SuggestedPaletteEntry>,
+}
+
+/// A color of a suggested palette, samples are stored at the palette's
+/// sample depth.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SuggestedPaletteEntry {
+    pub red: u16,
+    pub green: u16,
+    pub blue: u16,
+    pub alpha: u16,
+    /// Relative usage frequency of the color in the image.
+    pub frequency: u16,
+}
+
+/// Textual metadata from a tEXt or zTXt chunk.
+#[derive(Clone, Debug)]
+pub struct TextEntry {
+    pub keyword: String,
+    pub text: String,
+    /// Whether the text was stored zlib compressed (zTXt).
+    pub compressed: bool,
+}
+
+/// International text from an iTXt chunk.
+#[derive(Clone, Debug)]
+pub struct ITextEntry {
+    pub keyword: String,
+    /// Whether the text was stored zlib compressed.
+    pub compressed: bool,
+    /// Language tag such as `en-US`, empty when unspecified.
+    pub language: String,
+    /// The keyword translated into the language of the text.
+    pub translated_keyword: String,
+    pub text: String,
+}
+
+#[derive(Clone)]
+pub struct Chunk {
     size: u32,
-    type_: String,
+    type_: [u8; 4],
     data: Vec<u8>,
     crc: u32,
 }
 
-#[derive(Default)]
+impl Chunk {
+    /// The four chunk type bytes, e.g. `b"IHDR"`.
+    pub fn chunk_type(&self) -> &[u8; 4] {
+        &self.type_
+    }
+
+    /// The chunk type as text for display, bytes that aren't valid UTF-8 are
+    /// replaced.
+    pub fn type_str(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.type_)
+    }
+
+    /// Length of the chunk data in bytes.
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Mutable access to the chunk data for editing. The size and CRC are
+    /// left as they are until `recompute_crc` is called.
+    pub fn data_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.data
+    }
+
+    /// CRC stored in the file for this chunk.
+    pub fn crc(&self) -> u32 {
+        self.crc
+    }
+
+    /// Ancillary chunks (lowercase first letter) can be ignored by decoders
+    /// that don't know them, critical ones can't.
+    pub fn is_ancillary(&self) -> bool {
+        self.type_[0] & 0x20 != 0
+    }
+
+    /// Private chunks (lowercase second letter) aren't defined by the PNG
+    /// specification.
+    pub fn is_private(&self) -> bool {
+        self.type_[1] & 0x20 != 0
+    }
+
+    /// The reserved bit (case of the third letter), uppercase in every valid
+    /// chunk type.
+    pub fn is_reserved_bit_set(&self) -> bool {
+        self.type_[2] & 0x20 != 0
+    }
+
+    /// Safe-to-copy chunks (lowercase fourth letter) may be copied unchanged
+    /// by editors that don't know them, even after modifying critical chunks.
+    pub fn is_safe_to_copy(&self) -> bool {
+        self.type_[3] & 0x20 != 0
+    }
+
+    /// Whether the stored CRC matches the one computed over the chunk type
+    /// and data.
+    pub fn crc_is_valid(&self) -> bool {
+        self.computed_crc() == self.crc
+    }
+
+    /// Update the size and CRC to match the current data, e.g. after
+    /// editing it through `data_mut`.
+    pub fn recompute_crc(&mut self) {
+        self.size = self.data.len() as u32;
+        self.crc = self.computed_crc();
+    }
+
+    fn computed_crc(&self) -> u32 {
+        let mut crc = Crc32::new();
+        crc.update(&self.type_);
+        crc.update(&self.data);
+        crc.finalize()
+    }
+}
+
+/// Type, size and CRC status of a chunk, see `PNGFile::iter_chunks`.
+pub struct ChunkHeader {
+    pub type_: [u8; 4],
+    pub size: u32,
+    pub crc_ok: bool,
+}
+
+impl ChunkHeader {
+    /// The chunk type as text for display, see `Chunk::type_str`.
+    pub fn type_str(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.type_)
+    }
+}
+
+#[derive(Clone, Default)]
 pub struct PNGFile {
-    file: Option<File>,
     data: Vec<u8>,
-    size: u32,
+    image_data: Vec<u8>,
     width: u32,
     height: u32,
-    pallette: Vec<RGB>,
+    pallette: Vec<Rgb>,
+    transparency: Option<Transparency>,
+    gamma: Option<f64>,
+    srgb_intent: Option<u8>,
+    chromaticities: Option<Chromaticities>,
+    /// Profile name and decompressed profile from the iCCP chunk, `None` for
+    /// a profile that doesn't decompress.
+    icc_profile: Option<(String, Option<Vec<u8>>)>,
+    significant_bits: Option<Vec<u8>>,
+    palette_histogram: Option<Vec<u16>>,
+    modified: Option<TimeStamp>,
+    suggested_palettes: Vec<SuggestedPalette>,
+    text: Vec<TextEntry>,
+    international_text: Vec<ITextEntry>,
+    physical: Option<(u32, u32, u8)>,
+    background: Option<Rgb16>,
+    animation: Option<Apng>,
     bit_depth: u8,
-    color_type: u8,
+    color_type: ColorType,
     filter_method: u8,
     compression_method: u8,
     interlace_method: u8,
-    chunks: HashMap<usize, Chunk>,
-}
-
-static mut CRC_TABLE: [u32; 256] = [0; 256];
-static CRC_TABLE_INIT: Once = Once::new();
-
-/// Initialize the CRC table for faster computation
-fn make_crc_table() {
-    // Safety: This is safe because we use Once to ensure single initialization
-    // and this is only called in a synchronized context
-    unsafe {
-        CRC_TABLE_INIT.call_once(|| {
-            for n in 0..256 {
-                let mut c = n as u32;
-                for _ in 0..8 {
-                    if c & 1 != 0 {
-                        c = 0xedb88320u32 ^ (c >> 1);
-                    } else {
-                        c = c >> 1;
-                    }
-                }
-                CRC_TABLE[n] = c;
+    chunks: Vec<Chunk>,
+    /// Every chunk as read except the image data (IDAT and fdAT), parsed or
+    /// not, in file order.
+    raw_chunks: Vec<Chunk>,
+    ancillary_count: usize,
+    crc_errors: Vec<PNGParseError>,
+    trailing_bytes: Vec<u8>,
+}
+
+/// Configurable PNG decoder producing a `PNGFile`, the defaults are the ones
+/// used by `PNGFile::from_reader`.
+#[derive(Clone, Default)]
+pub struct Decoder {
+    ignore_crc: bool,
+    max_dimensions: Option<(u32, u32)>,
+    max_decompressed_size: Option<usize>,
+    max_metadata_size: Option<usize>,
+    parallel_crc: bool,
+    keep_trailing_bytes: bool,
+    clamp_palette_indices: bool,
+    max_chunks: Option<usize>,
+}
+
+impl Decoder {
+    pub fn new() -> Decoder {
+        Decoder::default()
+    }
+
+    /// Accept chunks whose CRC doesn't match their contents instead of
+    /// failing, to decode slightly corrupt files. The CRCs aren't computed
+    /// at all then, which also saves the checksum cost on trusted input.
+    /// Off by default.
+    pub fn ignore_crc(mut self, ignore_crc: bool) -> Decoder {
+        self.ignore_crc = ignore_crc;
+        self
+    }
+
+    /// Refuse images wider than `width` or taller than `height` pixels.
+    pub fn max_dimensions(mut self, width: u32, height: u32) -> Decoder {
+        self.max_dimensions = Some((width, height));
+        self
+    }
+
+    /// Maximum number of bytes the IDAT stream may inflate to. Defaults to
+    /// the size the image dimensions imply, so crafted streams that expand
+    /// far beyond the image can't exhaust memory.
+    pub fn max_decompressed_size(mut self, bytes: usize) -> Decoder {
+        self.max_decompressed_size = Some(bytes);
+        self
+    }
+
+    /// Maximum number of bytes the compressed text of a zTXt or iTXt chunk
+    /// or the profile of an iCCP chunk may inflate to, each. Defaults to
+    /// 8 MiB.
+    pub fn max_metadata_size(mut self, bytes: usize) -> Decoder {
+        self.max_metadata_size = Some(bytes);
+        self
+    }
+
+    /// Read all chunks up front and verify their CRCs on several threads
+    /// before decoding. Same results as the default serial check, faster
+    /// for large files at the cost of holding every chunk in memory at once.
+    pub fn parallel_crc(mut self, parallel_crc: bool) -> Decoder {
+        self.parallel_crc = parallel_crc;
+        self
+    }
+
+    /// Read whatever follows the IEND chunk into `PNGFile::trailing_bytes`.
+    /// Off by default, parsing stops at IEND and leaves the rest unread.
+    pub fn keep_trailing_bytes(mut self, keep_trailing_bytes: bool) -> Decoder {
+        self.keep_trailing_bytes = keep_trailing_bytes;
+        self
+    }
+
+    /// Maximum number of chunks other than IDAT and fdAT a file may have,
+    /// so a stream of tiny chunks can't exhaust memory. Defaults to 10000.
+    pub fn max_chunks(mut self, max_chunks: usize) -> Decoder {
+        self.max_chunks = Some(max_chunks);
+        self
+    }
+
+    /// Replace palette indices without a PLTE entry by the last entry
+    /// instead of failing. Off by default, such indices are an error.
+    pub fn clamp_palette_indices(mut self, clamp_palette_indices: bool) -> Decoder {
+        self.clamp_palette_indices = clamp_palette_indices;
+        self
+    }
+
+    /// Parse a whole PNG stream from `reader`.
+    pub fn decode<R: Read>(&self, reader: R) -> Result<PNGFile, PNGParseError> {
+        self.decode_with_callback(reader, |_| {})
+    }
+
+    /// Like `decode`, but calls `on_chunk` with the header of every chunk as
+    /// it is read, before its contents are decoded, e.g. to report progress.
+    /// `crc_ok` is always true when CRCs are ignored.
+    pub fn decode_with_callback<R: Read, F: FnMut(&ChunkHeader)>(
+        &self,
+        mut reader: R,
+        mut on_chunk: F,
+    ) -> Result<PNGFile, PNGParseError> {
+        let mut png_file = PNGFile::default();
+        png_file.parse_reader(&mut reader, self, &mut on_chunk)?;
+        Ok(png_file)
+    }
+
+    /// Like `decode`, but calls `on_progress` with the number of bytes read
+    /// so far and `total_bytes` after every chunk, e.g. to drive a progress
+    /// bar. `total_bytes` is typically the file length, the count reaches
+    /// it with the IEND chunk unless data follows IEND.
+    pub fn decode_with_progress<R: Read, F: FnMut(u64, u64)>(
+        &self,
+        reader: R,
+        total_bytes: u64,
+        mut on_progress: F,
+    ) -> Result<PNGFile, PNGParseError> {
+        let mut bytes_read = PNG_SIGNATURE.len() as u64;
+        self.decode_with_callback(reader, |header| {
+            bytes_read += 12 + header.size as u64;
+            on_progress(bytes_read, total_bytes);
+        })
+    }
+}
+
+/// How the encoder picks the filter type of each scanline.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum FilterStrategy {
+    /// No filtering, same as `Fixed(0)`.
+    None,
+    /// The same filter type (0-4) for every scanline.
+    Fixed(u8),
+    /// Try all five filters on each scanline and keep the one with the
+    /// smallest sum of absolute values, read as signed bytes.
+    #[default]
+    MinSumOfAbsolute,
+}
+
+/// Configurable PNG encoder, the defaults are the ones used by
+/// `PNGFile::write`.
+#[derive(Clone, Default)]
+pub struct Encoder {
+    filter: FilterStrategy,
+    copy_safe_chunks: bool,
+}
+
+impl Encoder {
+    pub fn new() -> Encoder {
+        Encoder::default()
+    }
+
+    /// Scanline filter selection, adaptive by default.
+    pub fn filter(mut self, filter: FilterStrategy) -> Encoder {
+        self.filter = filter;
+        self
+    }
+
+    /// Also write the ancillary chunks whose type marks them safe to copy,
+    /// parsed (pHYs, sPLT and the text chunks) or unknown, so rewriting a
+    /// file keeps its metadata. Off by default.
+    pub fn copy_safe_chunks(mut self, copy_safe_chunks: bool) -> Encoder {
+        self.copy_safe_chunks = copy_safe_chunks;
+        self
+    }
+
+    /// Encode `image` as a PNG stream: signature, IHDR, PLTE for paletted
+    /// images, tRNS when the image has one, the copied chunks if enabled, a
+    /// single zlib compressed IDAT and IEND, without interlacing.
+    pub fn encode<W: Write>(&self, image: &PNGFile, writer: &mut W) -> Result<(), PNGParseError> {
+        image.write_with(writer, self)
+    }
+}
+
+/// Iterator over the decoded pixels of a `PNGFile`, see `PNGFile::pixels`.
+pub struct Pixels<'a> {
+    image: &'a PNGFile,
+    index: usize,
+    len: usize,
+}
+
+impl Iterator for Pixels<'_> {
+    type Item = (u32, u32, [u8; 4]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.index >= self.len {
+            return None;
+        }
+        let width = self.image.width as usize;
+        let x = (self.index % width) as u32;
+        let y = (self.index / width) as u32;
+        self.index += 1;
+        // a pixel that can't be expanded (e.g. a dangling palette index)
+        // comes out as transparent black so the length stays exact
+        let pixel = self.image.get_pixel(x, y).unwrap_or([0; 4]);
+        Some((x, y, pixel))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.len - self.index;
+        (remaining, Some(remaining))
+    }
+}
+
+impl ExactSizeIterator for Pixels<'_> {}
+
+pub(crate) const PNG_SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+
+/// Largest chunk length the specification allows, 2^31-1 bytes.
+pub(crate) const MAX_CHUNK_LEN: u32 = 0x7FFF_FFFF;
+
+/// Default for `Decoder::max_chunks`.
+const DEFAULT_MAX_CHUNKS: usize = 10_000;
+
+/// Default for `Decoder::max_metadata_size`.
+const DEFAULT_MAX_METADATA_SIZE: usize = 8 << 20;
+
+/// Filtered image size from which defiltering Up-only images is split
+/// across threads, below it spawning them costs more than it saves.
+const PARALLEL_UNFILTER_MIN_LEN: usize = 1 << 20;
+
+/// Position in the chunk sequence, used to enforce the chunk ordering rules.
+#[derive(Clone, Copy, PartialEq)]
+enum ChunkStage {
+    Start,
+    Header,
+    ImageData,
+    AfterImageData,
+    End,
+}
+
+/// State carried from one chunk to the next while parsing.
+struct ParseState {
+    stage: ChunkStage,
+    /// Concatenated data of the IDAT chunks.
+    data_chunks: Vec<u8>,
+    /// Compressed data of each animation frame, from its fdAT chunks.
+    frame_data: Vec<Vec<u8>>,
+}
+
+/// Chunks that have to appear before the first IDAT chunk.
+const BEFORE_IDAT: [&[u8; 4]; 12] = [
+    b"PLTE", b"tRNS", b"gAMA", b"pHYs", b"bKGD", b"acTL", b"sRGB", b"cHRM", b"iCCP", b"sBIT",
+    b"sPLT", b"hIST",
+];
+
+/// Whether a chunk of this type counts toward `Decoder::max_chunks`. Image
+/// data is appended to one buffer, the other chunks are kept one by one.
+fn counts_toward_max_chunks(type_: &[u8; 4]) -> bool {
+    type_ != b"IDAT" && type_ != b"fdAT"
+}
+
+/// Advance the ordering state with the next chunk type, erroring when the
+/// chunk is out of place.
+fn next_stage(stage: ChunkStage, type_: &[u8; 4]) -> Result<ChunkStage, PNGParseError> {
+    match (stage, type_) {
+        (ChunkStage::Start, b"IHDR") => Ok(ChunkStage::Header),
+        (ChunkStage::Start, _) => Err(PNGParseError::ParseError("IHDR must be the first chunk.")),
+        (ChunkStage::End, _) => Err(PNGParseError::ParseError("Chunk found after IEND.")),
+        (_, b"IHDR") => Err(PNGParseError::ParseError("Duplicate IHDR chunk.")),
+        (ChunkStage::Header | ChunkStage::ImageData, b"IDAT") => Ok(ChunkStage::ImageData),
+        (ChunkStage::AfterImageData, b"IDAT") => {
+            Err(PNGParseError::ParseError("IDAT chunks must be contiguous."))
+        }
+        (_, b"IEND") => Ok(ChunkStage::End),
+        (ChunkStage::ImageData | ChunkStage::AfterImageData, type_) if BEFORE_IDAT.contains(&type_) => {
+            Err(PNGParseError::ParseError("Chunk must come before IDAT."))
+        }
+        (ChunkStage::ImageData, _) => Ok(ChunkStage::AfterImageData),
+        (stage, _) => Ok(stage),
+    }
+}
+
+/// Adam7 passes as (x offset, y offset, x step, y step).
+const ADAM7_PASSES: [(usize, usize, usize, usize); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+/// CRC table for faster computation, built at compile time.
+const CRC_TABLE: [u32; 256] = make_crc_table();
+
+const fn make_crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            if c & 1 != 0 {
+                c = 0xedb88320u32 ^ (c >> 1);
+            } else {
+                c >>= 1;
             }
-        });
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
     }
+    table
 }
 
 /// Update a running CRC with the bytes from the buffer.
 /// The CRC should be initialized to all 1's, and the transmitted value
 /// is the 1's complement of the final running CRC.
 pub fn update_crc(mut crc: u32, buf: &[u8]) -> u32 {
-    make_crc_table();
-
-    // Safety: This is safe because we've initialized the table
-    // and we're only reading from it
-    unsafe {
-        for &byte in buf {
-            crc = CRC_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8);
-        }
+    for &byte in buf {
+        crc = CRC_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8);
     }
     crc
 }
 
-/// Calculate the CRC for the given buffer
-pub fn crc(buf: &[u8]) -> u32 {
-    update_crc(0xffffffff, buf) ^ 0xffffffff
+/// Incremental CRC computation over data that arrives in pieces, gives the
+/// same result as `crc` over the concatenated bytes.
+#[derive(Clone, Copy)]
+pub struct Crc32 {
+    crc: u32,
 }
 
-impl PNGFile {
-    pub fn init(filepath: String) -> PNGFile {
-        let input_file = File::open(filepath).unwrap();
-        let png_file = PNGFile {
-            file: Some(input_file),
-            ..Default::default()
-        };
-        png_file
-    }
-
-    pub fn parse(self: &mut Self) -> Result<(), PNGParseError> {
-        match &mut self.file {
-            Some(f) => {
-                // verify header
-                let mut png_header: [u8; 8] = [0; 8];
-                let png_signiture: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
-                let _ = f.read(&mut png_header).unwrap_or(0);
-                if png_header != png_signiture {
-                    return Err(PNGParseError::ParseError(
-                        "Invalid png file, wrong signiture.",
-                    ));
-                }
-                let mut data_chunks: Vec<u8> = vec![];
-                // reading chuncks
-                self.chunks = HashMap::new();
-                let mut i: usize = 0;
-                while let Ok(chunk) = PNGFile::read_chunk(f) {
-                    if chunk.type_ == "IHDR" && i == 0 {
-                        let width_buf: [u8; 4] = chunk
-                            .data
-                            .get(0..4)
-                            .unwrap_or(&[0 as u8; 4])
-                            .try_into()
-                            .unwrap_or([0 as u8; 4]);
-                        self.width = u32::from_be_bytes(width_buf);
-                        //must verify width else error
-                        let height_buf: [u8; 4] = chunk
-                            .data
-                            .get(4..8)
-                            .unwrap_or(&[0 as u8; 4])
-                            .try_into()
-                            .unwrap_or([0 as u8; 4]);
-                        self.height = u32::from_be_bytes(height_buf);
-                        //must verify height else error
-                        self.bit_depth = *(chunk.data.get(8).unwrap_or(&0));
-                        self.color_type = *(chunk.data.get(9).unwrap_or(&0));
-                        self.compression_method = *(chunk.data.get(10).unwrap_or(&0));
-                        self.filter_method = *(chunk.data.get(11).unwrap_or(&0));
-                        self.interlace_method = *(chunk.data.get(12).unwrap_or(&0));
-                    } else if chunk.type_ == "IDAT" {
-                        let mut data = chunk.data.clone();
-                        data_chunks.append(&mut data);
-                        assert!(data_chunks.len() > 0);
-                    } else if chunk.type_ == "PLTE" {
-                        for i in 0..chunk.data.len() {
-                            let rgb_bytes = &chunk.data[i..i + 3];
-                            let rgb_entry = RGB {
-                                r: rgb_bytes[0],
-                                g: rgb_bytes[1],
-                                b: rgb_bytes[2],
-                            };
-                            self.pallette.push(rgb_entry);
-                        }
-                    } else if chunk.type_ == "IEND" {
-                        assert!(data_chunks.len() > 0);
-                        let mut dec = ZlibDecoder::new(&data_chunks[..]);
-                        let mut deflated_data: Vec<u8> = vec![];
-                        dec.read_to_end(&mut deflated_data).unwrap();
-                        assert!(deflated_data.len() > 0);
-                        self.data.append(&mut deflated_data);
-                    } else {
-                        self.chunks.insert(i, chunk);
-                    }
-                    i += 1;
-                }
+impl Default for Crc32 {
+    fn default() -> Self {
+        Crc32::new()
+    }
+}
 
-                println!("width:{} height:{} bit_depth: {}", self.width, self.height, self.bit_depth);
-                println!("data: {}", self.data.len());
+impl Crc32 {
+    pub fn new() -> Crc32 {
+        Crc32 { crc: 0xffffffff }
+    }
 
-                println!("chunks lenght: {}", self.chunks.len());
+    pub fn update(&mut self, buf: &[u8]) {
+        self.crc = update_crc(self.crc, buf);
+    }
 
-                for (i, chunk) in self.chunks.iter() {
-                    println!(
-                        "chunk:\t index: {} size:{} type:{}",
-                        i, chunk.size, chunk.type_
-                    );
-                }
+    pub fn finalize(self) -> u32 {
+        self.crc ^ 0xffffffff
+    }
+}
 
-                Ok(())
-            }
-            None => Ok(()),
+/// Read until `buf` is full or the reader runs out of data and return the
+/// number of bytes read. Unlike `read_exact`, running out of data is not an
+/// error so callers can tell a clean EOF apart from an I/O failure.
+fn read_full<R: Read + ?Sized>(reader: &mut R, buf: &mut [u8]) -> Result<usize, std::io::Error> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
         }
     }
+    Ok(filled)
+}
 
-    fn read_chunk(file: &mut File) -> Result<Chunk, PNGParseError> {
-        let mut chunk_st: [u8; 4] = [0; 4];
-        let mut bytes_read = file.read(&mut chunk_st).unwrap();
-        //println!("bytes read: {}", bytes_read);
-        if bytes_read != chunk_st.len() {
+/// Whether `bytes` starts with the PNG signature, a cheap check for
+/// sniffing file types without decoding anything.
+pub fn is_png(bytes: &[u8]) -> bool {
+    bytes.starts_with(&PNG_SIGNATURE)
+}
+
+/// Read the eight byte PNG signature, failing if it doesn't match.
+fn read_signature<R: Read + ?Sized>(reader: &mut R) -> Result<(), PNGParseError> {
+    let mut png_header: [u8; 8] = [0; 8];
+    read_full(reader, &mut png_header)?;
+    if !is_png(&png_header) {
+        return Err(PNGParseError::ParseError(
+            "Invalid png file, wrong signiture.",
+        ));
+    }
+    Ok(())
+}
+
+/// Read the next chunk, streaming its data through the CRC instead of
+/// keeping it. `None` on a clean EOF before the chunk starts.
+fn read_chunk_header<R: Read>(reader: &mut R) -> Result<Option<ChunkHeader>, PNGParseError> {
+    let mut size_buf = [0; 4];
+    match read_full(reader, &mut size_buf)? {
+        0 => return Ok(None),
+        4 => {}
+        _ => return Err(PNGParseError::EOF),
+    }
+    let size = u32::from_be_bytes(size_buf);
+    if size > MAX_CHUNK_LEN {
+        return Err(PNGParseError::ParseError("Chunk length exceeds 2^31-1 bytes."));
+    }
+    let mut type_buf = [0; 4];
+    if read_full(reader, &mut type_buf)? != type_buf.len() {
+        return Err(PNGParseError::EOF);
+    }
+    let mut crc = Crc32::new();
+    crc.update(&type_buf);
+    let mut buf = [0; 4096];
+    let mut remaining = size as usize;
+    while remaining > 0 {
+        let len = remaining.min(buf.len());
+        if read_full(reader, &mut buf[..len])? != len {
             return Err(PNGParseError::EOF);
         }
-        let chunk_size_int = u32::from_be_bytes(chunk_st);
-        let mut chunk_type_buf = [0; 4];
-        bytes_read = file.read(&mut chunk_type_buf).unwrap();
-        //println!("bytes read: {}", bytes_read);
-        if bytes_read != chunk_st.len() {
-            return Err(PNGParseError::EOF);
+        crc.update(&buf[..len]);
+        remaining -= len;
+    }
+    let mut crc_buf = [0; 4];
+    if read_full(reader, &mut crc_buf)? != crc_buf.len() {
+        return Err(PNGParseError::EOF);
+    }
+    Ok(Some(ChunkHeader {
+        type_: type_buf,
+        size,
+        crc_ok: crc.finalize() == u32::from_be_bytes(crc_buf),
+    }))
+}
+
+/// Split chunk data at the first null byte into what comes before and
+/// after it, `None` if there is no null byte.
+fn split_at_null(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    let separator = data.iter().position(|b| *b == 0)?;
+    Some((&data[..separator], &data[separator + 1..]))
+}
+
+/// Why a bounded inflate failed.
+enum InflateError {
+    Invalid,
+    Truncated,
+    TooLarge,
+}
+
+/// Decompress the zlib stream `compressed`, failing once the output grows
+/// past `limit` bytes.
+fn inflate_bounded(compressed: &[u8], limit: usize) -> Result<Vec<u8>, InflateError> {
+    let mut inflater = Decompress::new(true);
+    let mut inflated: Vec<u8> = vec![];
+    loop {
+        // grow the output by at most one byte past the limit to tell if it
+        // was exceeded
+        inflated.reserve((limit.saturating_add(1) - inflated.len()).min(1 << 16));
+        let remaining = &compressed[inflater.total_in() as usize..];
+        let status = inflater
+            .decompress_vec(remaining, &mut inflated, FlushDecompress::None)
+            .map_err(|_| InflateError::Invalid)?;
+        if inflated.len() > limit {
+            return Err(InflateError::TooLarge);
         }
-        let chunk_type = match std::str::from_utf8(&chunk_type_buf) {
-            Ok(s) => s,
-            _ => "",
-        };
-        let mut chunk_data = vec![0; chunk_size_int as usize];
-        bytes_read = file.read(&mut chunk_data).unwrap();
-        //println!("bytes read: {}", bytes_read);
-        if bytes_read != chunk_size_int as usize {
-            return Err(PNGParseError::EOF);
+        if status == Status::StreamEnd {
+            return Ok(inflated);
         }
-
-        let mut chunk_crc_buf = [0; 4];
-        bytes_read = file.read(&mut chunk_crc_buf).unwrap();
-        //println!("bytes read: {}", bytes_read);
-        if bytes_read != chunk_st.len() {
-            return Err(PNGParseError::EOF);
+        // with room left in the output the inflater only stops once the
+        // input runs out, before the final block
+        if inflated.len() < inflated.capacity() {
+            return Err(InflateError::Truncated);
         }
-        let chunk_crc = u32::from_be_bytes(chunk_crc_buf);
+    }
+}
 
-        let mut crc_buffer: Vec<u8> = Vec::new();
-        crc_buffer.append(&mut chunk_type_buf.clone().to_vec());
-        crc_buffer.append(&mut chunk_data.clone().to_vec());
-        if crc(&crc_buffer) != chunk_crc {
-            return Err(PNGParseError::ParseError("Invalid CRC"));
-        }
-        let chunk = Chunk {
-            type_: String::from(chunk_type),
-            size: chunk_size_int,
-            data: chunk_data,
-            crc: chunk_crc,
-        };
-        Ok(chunk)
+/// Decompress a zlib stream embedded in a metadata chunk, at most `limit`
+/// bytes of it.
+fn inflate(data: &[u8], limit: usize) -> Result<Vec<u8>, PNGParseError> {
+    inflate_bounded(data, limit).map_err(|e| match e {
+        InflateError::TooLarge => PNGParseError::ParseError("Chunk data exceeds the metadata size limit."),
+        InflateError::Invalid | InflateError::Truncated => PNGParseError::ParseError("Invalid zlib stream."),
+    })
+}
+
+/// Decode an iTXt chunk: keyword, compression flag and method, language
+/// tag, translated keyword and the (possibly compressed) UTF-8 text.
+fn read_international_text(data: &[u8], limit: usize) -> Result<ITextEntry, PNGParseError> {
+    let missing_separator = || PNGParseError::ParseError("Invalid iTXt chunk, missing separator.");
+    let (keyword, rest) = split_at_null(data).ok_or_else(missing_separator)?;
+    let (compressed, rest) = match rest {
+        [0, _, rest @ ..] => (false, rest),
+        [1, 0, rest @ ..] => (true, rest),
+        [1, _, ..] => return Err(PNGParseError::ParseError("Unknown iTXt compression method.")),
+        _ => return Err(PNGParseError::ParseError("Invalid iTXt chunk.")),
+    };
+    let (language, rest) = split_at_null(rest).ok_or_else(missing_separator)?;
+    let (translated_keyword, text) = split_at_null(rest).ok_or_else(missing_separator)?;
+    let text = if compressed { inflate(text, limit)? } else { text.to_vec() };
+    let utf8 = |bytes: &[u8]| {
+        String::from_utf8(bytes.to_vec())
+            .map_err(|_| PNGParseError::ParseError("Invalid UTF-8 in iTXt chunk."))
+    };
+    Ok(ITextEntry {
+        keyword: latin1_to_string(keyword),
+        compressed,
+        language: latin1_to_string(language),
+        translated_keyword: utf8(translated_keyword)?,
+        text: utf8(&text)?,
+    })
+}
+
+/// Decode latin-1 (ISO 8859-1) bytes, each byte maps to the code point of
+/// the same value.
+fn latin1_to_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| *b as char).collect()
+}
+
+/// Encode text as Latin-1, characters outside of it become `?`.
+fn string_to_latin1(text: &str) -> Vec<u8> {
+    text.chars().map(|c| u8::try_from(c).unwrap_or(b'?')).collect()
+}
+
+fn deflate(data: &[u8]) -> Result<Vec<u8>, PNGParseError> {
+    let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+    zlib.write_all(data)?;
+    Ok(zlib.finish()?)
+}
+
+/// Decode an sPLT chunk: palette name, sample depth and the entries, each
+/// RGBA plus a frequency.
+fn read_suggested_palette(data: &[u8]) -> Result<SuggestedPalette, PNGParseError> {
+    let (name, rest) = split_at_null(data)
+        .ok_or(PNGParseError::ParseError("Invalid sPLT chunk, missing separator."))?;
+    let (sample_depth, entries) = rest
+        .split_first()
+        .ok_or(PNGParseError::ParseError("Invalid sPLT chunk."))?;
+    let entry_len = match sample_depth {
+        8 => 6,
+        16 => 10,
+        _ => return Err(PNGParseError::ParseError("Invalid sPLT sample depth.")),
+    };
+    if !entries.len().is_multiple_of(entry_len) {
+        return Err(PNGParseError::ParseError("Invalid sPLT chunk length."));
+    }
+    let entries = entries
+        .chunks_exact(entry_len)
+        .map(|entry| {
+            let sample = |i: usize| {
+                if *sample_depth == 8 {
+                    entry[i] as u16
+                } else {
+                    u16::from_be_bytes([entry[i * 2], entry[i * 2 + 1]])
+                }
+            };
+            SuggestedPaletteEntry {
+                red: sample(0),
+                green: sample(1),
+                blue: sample(2),
+                alpha: sample(3),
+                frequency: u16::from_be_bytes([entry[entry_len - 2], entry[entry_len - 1]]),
+            }
+        })
+        .collect();
+    Ok(SuggestedPalette {
+        name: latin1_to_string(name),
+        sample_depth: *sample_depth,
+        entries,
+    })
+}
+
+/// Calculate the CRC for the given buffer
+pub fn crc(buf: &[u8]) -> u32 {
+    update_crc(0xffffffff, buf) ^ 0xffffffff
+}
+
+/// The Paeth predictor picks whichever of the left (a), above (b) and
+/// upper left (c) bytes is closest to `a + b - c`. Ties are broken in the
+/// order a, b, c as the specification requires, so encoder and decoder must
+/// both use this exact function.
+pub(crate) fn paeth_predictor(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// Apply filter `filter_type` to a raw scanline, the inverse of
+/// `unfilter_scanline`.
+fn filter_scanline(filter_type: u8, bpp: usize, previous: &[u8], current: &[u8]) -> Vec<u8> {
+    (0..current.len())
+        .map(|i| {
+            let a = if i >= bpp { current[i - bpp] } else { 0 };
+            let b = previous[i];
+            let c = if i >= bpp { previous[i - bpp] } else { 0 };
+            let predictor = match filter_type {
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                4 => paeth_predictor(a, b, c),
+                _ => 0,
+            };
+            current[i].wrapping_sub(predictor)
+        })
+        .collect()
+}
+
+/// Split a scanline of packed sub-byte samples into one byte per sample.
+/// Samples are packed from the most significant bit down and the padding
+/// bits at the end of the scanline are dropped.
+fn unpack_samples(packed: &[u8], bit_depth: u8, count: usize) -> Vec<u8> {
+    let bits = bit_depth as usize;
+    let mask = (1u8 << bits) - 1;
+    (0..count)
+        .map(|i| {
+            let bit_offset = i * bits;
+            let shift = 8 - bits - bit_offset % 8;
+            (packed[bit_offset / 8] >> shift) & mask
+        })
+        .collect()
+}
+
+/// Inverse of `unpack_samples`: pack one-byte samples into a scanline of
+/// `bit_depth` bits per sample, padding the last byte with zero bits.
+fn pack_samples(samples: &[u8], bit_depth: u8) -> Vec<u8> {
+    let bits = bit_depth as usize;
+    let mut packed = vec![0u8; (samples.len() * bits).div_ceil(8)];
+    for (i, sample) in samples.iter().enumerate() {
+        let bit_offset = i * bits;
+        let shift = 8 - bits - bit_offset % 8;
+        packed[bit_offset / 8] |= sample << shift;
     }
+    packed
+}
+
+/// Write a single chunk: its length, type, data and the CRC over the type
+/// and data.
+fn write_chunk<W: Write>(writer: &mut W, type_: &[u8; 4], data: &[u8]) -> Result<(), PNGParseError> {
+    let mut crc = Crc32::new();
+    crc.update(type_);
+    crc.update(data);
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    writer.write_all(type_)?;
+    writer.write_all(data)?;
+    writer.write_all(&crc.finalize().to_be_bytes())?;
+    Ok(())
+}
 
+/// Reverse the filter of a single scanline in place.
+/// `previous` is the already reconstructed scanline above it (all zeroes for
+/// the first one) and `bpp` the number of bytes per complete pixel, at least 1.
+fn unfilter_scanline(
+    filter_type: u8,
+    bpp: usize,
+    previous: &[u8],
+    current: &mut [u8],
+) -> Result<(), PNGParseError> {
+    match filter_type {
+        // None
+        0 => {}
+        // Sub
+        1 => {
+            for i in bpp..current.len() {
+                current[i] = current[i].wrapping_add(current[i - bpp]);
+            }
+        }
+        // Up
+        2 => {
+            for (x, b) in current.iter_mut().zip(previous) {
+                *x = x.wrapping_add(*b);
+            }
+        }
+        // Average
+        3 => {
+            for i in 0..current.len() {
+                let a = if i >= bpp { current[i - bpp] } else { 0 };
+                let average = (a as u16 + previous[i] as u16) / 2;
+                current[i] = current[i].wrapping_add(average as u8);
+            }
+        }
+        // Paeth
+        4 => {
+            for i in 0..current.len() {
+                let (a, c) = if i >= bpp {
+                    (current[i - bpp], previous[i - bpp])
+                } else {
+                    (0, 0)
+                };
+                current[i] = current[i].wrapping_add(paeth_predictor(a, previous[i], c));
+            }
+        }
+        _ => return Err(PNGParseError::UnknownFilter(filter_type)),
+    }
+    Ok(())
+}
+
+/// Reconstruct `scanlines` on several threads when every one of them uses
+/// filter type None or Up. Up only depends on the byte above, so each thread
+/// can take a band of columns through all rows. Returns the rows without
+/// their filter-type bytes, `None` when the data is too small to be worth it
+/// or some row uses a filter that depends on its left neighbor.
+fn unfilter_up_parallel(scanlines: &[u8], row_len: usize) -> Option<Vec<u8>> {
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    unfilter_up_on_threads(scanlines, row_len, threads)
+}
+
+/// `unfilter_up_parallel` with an explicit thread count, `None` for fewer
+/// than two threads.
+fn unfilter_up_on_threads(scanlines: &[u8], row_len: usize, threads: usize) -> Option<Vec<u8>> {
+    if threads < 2 || row_len == 0 || scanlines.len() < PARALLEL_UNFILTER_MIN_LEN {
+        return None;
+    }
+    if !scanlines.chunks(row_len + 1).all(|scanline| matches!(scanline[0], 0 | 2)) {
+        return None;
+    }
+    let height = scanlines.len() / (row_len + 1);
+    let band_len = row_len.div_ceil(threads);
+    let bands: Vec<(usize, Vec<u8>)> = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..row_len)
+            .step_by(band_len)
+            .map(|start| {
+                let end = (start + band_len).min(row_len);
+                scope.spawn(move || {
+                    let width = end - start;
+                    let mut band = vec![0u8; width * height];
+                    for (y, scanline) in scanlines.chunks(row_len + 1).enumerate() {
+                        let (above, rest) = band.split_at_mut(y * width);
+                        let row = &mut rest[..width];
+                        row.copy_from_slice(&scanline[1 + start..1 + end]);
+                        if scanline[0] == 2 && y > 0 {
+                            for (x, b) in row.iter_mut().zip(&above[(y - 1) * width..]) {
+                                *x = x.wrapping_add(*b);
+                            }
+                        }
+                    }
+                    (start, band)
+                })
+            })
+            .collect();
+        handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+    });
+    let mut rows = vec![0u8; row_len * height];
+    for (start, band) in bands {
+        let width = band.len() / height;
+        for (y, band_row) in band.chunks(width).enumerate() {
+            let row_start = y * row_len + start;
+            rows[row_start..row_start + width].copy_from_slice(band_row);
+        }
+    }
+    Some(rows)
+}
+
+/// Pair each chunk with whether its CRC is valid, checking contiguous groups
+/// of chunks on up to `threads` threads.
+fn verify_crcs(chunks: Vec<Chunk>, threads: usize) -> Vec<(Chunk, bool)> {
+    let per_thread = chunks.len().div_ceil(threads.max(1)).max(1);
+    let crc_ok: Vec<bool> = std::thread::scope(|scope| {
+        let handles: Vec<_> = chunks
+            .chunks(per_thread)
+            .map(|group| scope.spawn(move || group.iter().map(Chunk::crc_is_valid).collect::<Vec<_>>()))
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect()
+    });
+    chunks.into_iter().zip(crc_ok).collect()
+}
+
+impl PNGFile {
+    /// Open and parse the PNG file at `path` with the default `Decoder`
+    /// settings. Failing to open the file is reported as `PNGParseError::Io`.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<PNGFile, PNGParseError> {
+        PNGFile::from_reader(File::open(path)?)
+    }
+
+    /// Open and parse the PNG file at `filepath`, kept for existing callers.
+    #[deprecated(note = "use `PNGFile::open`")]
+    pub fn init(filepath: String) -> Result<PNGFile, PNGParseError> {
+        PNGFile::open(filepath)
+    }
+
+    /// Does nothing, `init` and the other constructors already parse the
+    /// whole file. Kept for callers of the former `init` then `parse` API.
+    #[deprecated(note = "the constructors parse the file")]
+    pub fn parse(&mut self) -> Result<(), PNGParseError> {
+        Ok(())
+    }
+
+    /// Build an image from already decoded samples laid out like the
+    /// defiltered data (see `scanlines`).
+    pub(crate) fn with_image_data(
+        width: u32,
+        height: u32,
+        color_type: ColorType,
+        bit_depth: u8,
+        image_data: Vec<u8>,
+    ) -> PNGFile {
+        PNGFile {
+            width,
+            height,
+            bit_depth,
+            color_type,
+            image_data,
+            ..Default::default()
+        }
+    }
+
+    /// Build an 8-bit RGBA image from already decoded, row-major pixels.
+    pub(crate) fn with_rgba8(width: u32, height: u32, pixels: Vec<u8>) -> PNGFile {
+        PNGFile::with_image_data(width, height, ColorType::Rgba, 8, pixels)
+    }
+
+    /// Create an 8-bit RGBA image from row-major pixels, four bytes per
+    /// pixel, e.g. to encode it with `write`. The inverse of `into_rgba8`.
+    pub fn from_rgba8(width: u32, height: u32, pixels: Vec<u8>) -> Result<PNGFile, PNGParseError> {
+        let image = PNGFile::with_rgba8(width, height, pixels);
+        if image.image_data.len() as u64 != image.byte_size() {
+            return Err(PNGParseError::ParseError("Pixel data doesn't match the image dimensions."));
+        }
+        image.validate_header()?;
+        Ok(image)
+    }
+
+    /// Parse a PNG stream from any reader, e.g. an in-memory buffer or stdin.
+    /// Uses the default `Decoder` settings.
+    pub fn from_reader<R: Read>(reader: R) -> Result<PNGFile, PNGParseError> {
+        Decoder::new().decode(reader)
+    }
+
+    /// Parse a PNG already held in memory, e.g. an HTTP response body.
+    pub fn from_bytes(bytes: &[u8]) -> Result<PNGFile, PNGParseError> {
+        PNGFile::from_reader(std::io::Cursor::new(bytes))
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Width and height together.
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// Number of pixels, computed in `u64` so it can't overflow.
+    pub fn pixel_count(&self) -> u64 {
+        self.width as u64 * self.height as u64
+    }
+
+    /// Size in bytes of the image decoded to RGBA8, see `into_rgba8`.
+    pub fn byte_size(&self) -> u64 {
+        self.pixel_count() * 4
+    }
+
+    /// Bits per sample (or per palette index), one of 1, 2, 4, 8 or 16.
+    pub fn bit_depth(&self) -> u8 {
+        self.bit_depth
+    }
+
+    /// Color type from IHDR: `ColorType::Grayscale`, `Rgb`, `Palette`,
+    /// `GrayscaleAlpha` or `Rgba`.
+    pub fn color_type(&self) -> ColorType {
+        self.color_type
+    }
+
+    /// Name of the color type, e.g. `RGBA`.
+    pub fn color_type_name(&self) -> &'static str {
+        self.color_type.name()
+    }
+
+    /// Whether pixels can be (partly) transparent: the color type has an
+    /// alpha channel or the image has a tRNS chunk.
+    pub fn has_alpha(&self) -> bool {
+        matches!(self.color_type, ColorType::GrayscaleAlpha | ColorType::Rgba) || self.transparency.is_some()
+    }
+
+    /// Number of samples per pixel for the color type.
+    pub fn channels(&self) -> u8 {
+        self.color_type.channels()
+    }
+
+    /// Bytes per complete pixel in a scanline, rounded up to 1 for sub-byte
+    /// depths. This is the distance filters look back for the left pixel.
+    pub fn bytes_per_pixel(&self) -> usize {
+        self.bits_per_pixel().div_ceil(8)
+    }
+
+    pub fn compression_method(&self) -> u8 {
+        self.compression_method
+    }
+
+    pub fn filter_method(&self) -> u8 {
+        self.filter_method
+    }
+
+    /// 0 for progressive images, 1 for Adam7 interlaced ones.
+    pub fn interlace_method(&self) -> u8 {
+        self.interlace_method
+    }
+
+    /// Whether the image is Adam7 interlaced.
+    pub fn is_interlaced(&self) -> bool {
+        self.interlace_method == 1
+    }
+
+    /// Palette entries from the PLTE chunk, empty if the file has none.
+    pub fn palette(&self) -> &[Rgb] {
+        &self.pallette
+    }
+
+    /// Transparency from the tRNS chunk, if any.
+    pub fn transparency(&self) -> Option<&Transparency> {
+        self.transparency.as_ref()
+    }
+
+    /// Chunks without dedicated handling, in file order.
+    pub fn chunks(&self) -> &[Chunk] {
+        &self.chunks
+    }
+
+    /// Mutable access to `chunks`, e.g. to edit their data before writing
+    /// the file. Call `repair_crcs` afterwards.
+    pub fn chunks_mut(&mut self) -> &mut [Chunk] {
+        &mut self.chunks
+    }
+
+    /// Recompute the size and CRC of every chunk in `chunks`, see
+    /// `Chunk::recompute_crc`.
+    pub fn repair_crcs(&mut self) {
+        self.chunks.iter_mut().for_each(Chunk::recompute_crc);
+    }
+
+    /// The ancillary chunks among `chunks`, in file order, e.g. private
+    /// metadata chunks an editor may want to carry over.
+    pub fn ancillary_chunks(&self) -> impl Iterator<Item = &Chunk> {
+        self.chunks.iter().filter(|chunk| chunk.is_ancillary())
+    }
+
+    /// The first chunk of the given type as it was read, parsed (IHDR, PLTE,
+    /// tEXt, pHYs, ...) or not. IDAT and fdAT chunks aren't kept and never
+    /// match, and edits made through `chunks_mut` don't show here.
+    pub fn chunk_by_type(&self, type_: &[u8; 4]) -> Option<&Chunk> {
+        self.raw_chunks.iter().find(|chunk| &chunk.type_ == type_)
+    }
+
+    /// All chunks of the given type as they were read, in file order, see
+    /// `chunk_by_type`.
+    pub fn chunks_by_type(&self, type_: &[u8; 4]) -> impl Iterator<Item = &Chunk> + '_ {
+        let type_ = *type_;
+        self.raw_chunks.iter().filter(move |chunk| chunk.type_ == type_)
+    }
+
+    /// Animation frames and their metadata if the file is an APNG.
+    pub fn animation(&self) -> Option<&Apng> {
+        self.animation.as_ref()
+    }
+
+    /// CRC mismatches found in ancillary chunks, which were skipped instead
+    /// of failing the parse. Mismatches in critical chunks are fatal.
+    pub fn crc_errors(&self) -> &[PNGParseError] {
+        &self.crc_errors
+    }
+
+    /// Bytes found after the IEND chunk, only kept when decoding with
+    /// `Decoder::keep_trailing_bytes`.
+    pub fn trailing_bytes(&self) -> &[u8] {
+        &self.trailing_bytes
+    }
+
+    /// Image gamma from the gAMA chunk, `None` if the file has no gAMA chunk.
+    pub fn gamma(&self) -> Option<f64> {
+        self.gamma
+    }
+
+    /// Rendering intent from the sRGB chunk, `None` if the file has no sRGB
+    /// chunk.
+    pub fn srgb_intent(&self) -> Option<RenderingIntent> {
+        self.srgb_intent.map(|intent| match intent {
+            0 => RenderingIntent::Perceptual,
+            1 => RenderingIntent::RelativeColorimetric,
+            2 => RenderingIntent::Saturation,
+            _ => RenderingIntent::AbsoluteColorimetric,
+        })
+    }
+
+    /// Suggested palettes from the sPLT chunks, in file order.
+    pub fn suggested_palettes(&self) -> &[SuggestedPalette] {
+        &self.suggested_palettes
+    }
+
+    /// Last modification time from the tIME chunk.
+    pub fn modified(&self) -> Option<TimeStamp> {
+        self.modified
+    }
+
+    /// Number of significant bits of each channel from the sBIT chunk, in
+    /// channel order (red, green, blue for paletted images).
+    pub fn significant_bits(&self) -> Option<&[u8]> {
+        self.significant_bits.as_deref()
+    }
+
+    /// Approximate usage frequency of each palette entry from the hIST
+    /// chunk, indexed like `palette`.
+    pub fn palette_histogram(&self) -> Option<&[u16]> {
+        self.palette_histogram.as_deref()
+    }
+
+    /// White point and primaries from the cHRM chunk.
+    pub fn chromaticities(&self) -> Option<Chromaticities> {
+        self.chromaticities
+    }
+
+    /// The embedded ICC profile from the iCCP chunk, decompressed. `None` if
+    /// the file has no iCCP chunk or the profile doesn't decompress.
+    pub fn icc_profile(&self) -> Option<Vec<u8>> {
+        self.icc_profile.as_ref().and_then(|(_, profile)| profile.clone())
+    }
+
+    /// Name of the embedded ICC profile.
+    pub fn icc_profile_name(&self) -> Option<&str> {
+        self.icc_profile.as_ref().map(|(name, _)| name.as_str())
+    }
+
+    /// Entries of the tEXt and zTXt chunks, in file order.
+    pub fn text_entries(&self) -> &[TextEntry] {
+        &self.text
+    }
+
+    /// Entries of the iTXt chunks, in file order.
+    pub fn international_text_entries(&self) -> &[ITextEntry] {
+        &self.international_text
+    }
+
+    /// Pixels per unit on the X and Y axes and the unit specifier from the
+    /// pHYs chunk. A unit of 1 means meters, 0 means the unit is unknown and
+    /// only the aspect ratio is meaningful.
+    pub fn physical(&self) -> Option<(u32, u32, u8)> {
+        self.physical
+    }
+
+    /// Horizontal and vertical resolution in dots per inch, only available
+    /// when pHYs gives the pixel density in pixels per meter.
+    pub fn dpi(&self) -> Option<(f64, f64)> {
+        match self.physical {
+            Some((x, y, 1)) => Some((x as f64 * 0.0254, y as f64 * 0.0254)),
+            _ => None,
+        }
+    }
+
+    /// Suggested background color from the bKGD chunk, scaled to the full
+    /// 16-bit range like the values of `get_pixel16`.
+    pub fn background(&self) -> Option<Rgb16> {
+        self.background
+    }
+
+    /// Lazily list the chunks of a PNG stream without decoding it or keeping
+    /// chunk data in memory, stopping after IEND or the first error.
+    pub fn iter_chunks<R: Read>(mut reader: R) -> impl Iterator<Item = Result<ChunkHeader, PNGParseError>> {
+        let mut started = false;
+        let mut done = false;
+        std::iter::from_fn(move || {
+            if done {
+                return None;
+            }
+            if !started {
+                started = true;
+                if let Err(e) = read_signature(&mut reader) {
+                    done = true;
+                    return Some(Err(e));
+                }
+            }
+            match read_chunk_header(&mut reader) {
+                Ok(Some(header)) => {
+                    done = &header.type_ == b"IEND";
+                    Some(Ok(header))
+                }
+                Ok(None) => None,
+                Err(e) => {
+                    done = true;
+                    Some(Err(e))
+                }
+            }
+        })
+    }
+
+    /// Read only the signature and IHDR of a PNG stream, leaving the rest of
+    /// the stream unread. The result has the header properties (size, bit
+    /// depth, color type, ...) but no image data.
+    pub fn read_info<R: Read>(mut reader: R) -> Result<PNGFile, PNGParseError> {
+        read_signature(&mut reader)?;
+        let offset = PNG_SIGNATURE.len() as u64;
+        let chunk = PNGFile::read_chunk(&mut reader, offset)?.ok_or(PNGParseError::EOF)?;
+        if &chunk.type_ != b"IHDR" {
+            return Err(PNGParseError::ParseError("IHDR must be the first chunk."));
+        }
+        if !chunk.crc_is_valid() {
+            return Err(PNGParseError::ParseError("Invalid CRC"));
+        }
+        let mut png_file = PNGFile::default();
+        png_file.read_header(&chunk.data)?;
+        Ok(png_file)
+    }
+
+    /// Decode and validate the IHDR chunk data.
+    fn read_header(&mut self, data: &[u8]) -> Result<(), PNGParseError> {
+        let data: &[u8; 13] = data
+            .try_into()
+            .map_err(|_| PNGParseError::ParseError("Invalid IHDR chunk length."))?;
+        self.width = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+        self.height = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
+        self.bit_depth = data[8];
+        self.color_type = ColorType::try_from(data[9])?;
+        self.compression_method = data[10];
+        self.filter_method = data[11];
+        self.interlace_method = data[12];
+        self.validate_header()
+    }
+
+    fn parse_reader(
+        &mut self,
+        reader: &mut dyn Read,
+        decoder: &Decoder,
+        on_chunk: &mut dyn FnMut(&ChunkHeader),
+    ) -> Result<(), PNGParseError> {
+        read_signature(reader)?;
+        let mut state = ParseState {
+            stage: ChunkStage::Start,
+            data_chunks: vec![],
+            frame_data: vec![],
+        };
+        // byte offset of the next chunk in the stream
+        let mut next_offset = PNG_SIGNATURE.len() as u64;
+        // the pixel data errors found after the loop point at the first IDAT
+        let mut idat_offset = None;
+        // reading chuncks
+        self.chunks = vec![];
+        self.ancillary_count = 0;
+        let max_chunks = decoder.max_chunks.unwrap_or(DEFAULT_MAX_CHUNKS);
+        let mut verified = if decoder.parallel_crc && !decoder.ignore_crc {
+            Some(PNGFile::read_chunks_verified(reader, max_chunks)?.into_iter())
+        } else {
+            None
+        };
+        let mut limited_chunks = 0;
+        loop {
+            let (chunk, crc_ok) = match verified.as_mut() {
+                Some(chunks) => match chunks.next() {
+                    Some(chunk) => chunk,
+                    None => break,
+                },
+                None => match PNGFile::read_chunk(reader, next_offset)? {
+                    Some(chunk) => {
+                        let crc_ok = decoder.ignore_crc || chunk.crc_is_valid();
+                        (chunk, crc_ok)
+                    }
+                    None => break,
+                },
+            };
+            if counts_toward_max_chunks(&chunk.type_) {
+                limited_chunks += 1;
+                if limited_chunks > max_chunks {
+                    return Err(PNGParseError::ParseError("Too many chunks."));
+                }
+            }
+            let offset = next_offset;
+            next_offset += 12 + chunk.size as u64;
+            if &chunk.type_ == b"IDAT" && idat_offset.is_none() {
+                idat_offset = Some(offset);
+            }
+            on_chunk(&ChunkHeader {
+                type_: chunk.type_,
+                size: chunk.size,
+                crc_ok,
+            });
+            let chunk_type = chunk.type_;
+            // ordering is checked even for chunks skipped below
+            let stage = next_stage(state.stage, &chunk_type).map_err(|e| e.in_chunk(&chunk_type, offset))?;
+            if !crc_ok {
+                let error = PNGParseError::ParseError("Invalid CRC").in_chunk(&chunk_type, offset);
+                // a corrupt ancillary chunk is skipped, the image is still usable
+                if !chunk.is_ancillary() {
+                    return Err(error);
+                }
+                self.crc_errors.push(error);
+                state.stage = stage;
+                continue;
+            }
+            self.read_chunk_contents(chunk, stage, &mut state, decoder)
+                .map_err(|e| e.in_chunk(&chunk_type, offset))?;
+            if state.stage == ChunkStage::End {
+                break;
+            }
+        }
+
+        if state.stage != ChunkStage::End {
+            return Err(PNGParseError::ParseError("Missing IEND chunk."));
+        }
+        if self.color_type == ColorType::Palette && self.pallette.is_empty() {
+            return Err(PNGParseError::ParseError("Missing PLTE chunk for indexed image."));
+        }
+        if decoder.keep_trailing_bytes {
+            reader.read_to_end(&mut self.trailing_bytes)?;
+        }
+
+        if !self.data.is_empty() {
+            let offset = idat_offset.unwrap_or_default();
+            self.image_data = self.defilter().map_err(|e| e.in_chunk(b"IDAT", offset))?;
+            self.check_palette_indices(decoder.clamp_palette_indices)
+                .map_err(|e| e.in_chunk(b"IDAT", offset))?;
+        }
+
+        if let Some(mut animation) = self.animation.take() {
+            if animation.num_frames as usize != animation.frames.len() {
+                return Err(PNGParseError::ParseError("Frame count doesn't match acTL."));
+            }
+            let frames = animation.frames.iter_mut().zip(&state.frame_data);
+            for (index, (frame, data)) in frames.enumerate() {
+                frame.pixels = if index == 0 && animation.default_image_is_first_frame {
+                    self.into_rgba8()?
+                } else {
+                    self.decode_frame(frame.width, frame.height, data, decoder)?
+                };
+            }
+            self.animation = Some(animation);
+        }
+
+        Ok(())
+    }
+
+    /// Decode the next chunk into the image properties, or keep it in
+    /// `chunks` if it has no dedicated handling. `stage` is the ordering
+    /// stage this chunk moves to, as checked by `next_stage`.
+    fn read_chunk_contents(
+        &mut self,
+        chunk: Chunk,
+        stage: ChunkStage,
+        state: &mut ParseState,
+        decoder: &Decoder,
+    ) -> Result<(), PNGParseError> {
+        if counts_toward_max_chunks(&chunk.type_) {
+            self.raw_chunks.push(chunk.clone());
+        }
+        let is_first_chunk = state.stage == ChunkStage::Start;
+        let metadata_limit = decoder.max_metadata_size.unwrap_or(DEFAULT_MAX_METADATA_SIZE);
+        state.stage = stage;
+        if chunk.is_ancillary() {
+            self.ancillary_count += 1;
+        }
+        if &chunk.type_ == b"IHDR" && is_first_chunk {
+            self.read_header(&chunk.data)?;
+            if let Some((max_width, max_height)) = decoder.max_dimensions {
+                if self.width > max_width || self.height > max_height {
+                    return Err(PNGParseError::ParseError("Image dimensions exceed the maximum."));
+                }
+            }
+        } else if &chunk.type_ == b"IDAT" {
+            // take over the first chunk's buffer, append later ones in
+            // place instead of cloning each chunk
+            if state.data_chunks.is_empty() {
+                state.data_chunks = chunk.data;
+            } else {
+                state.data_chunks.extend_from_slice(&chunk.data);
+            }
+            // IDAT chunks may be empty, only the concatenated data has to be
+            // non-empty, which IEND checks
+        } else if &chunk.type_ == b"PLTE" {
+            if !self.pallette.is_empty() {
+                return Err(PNGParseError::ParseError("Duplicate PLTE chunk."));
+            }
+            if matches!(self.color_type, ColorType::Grayscale | ColorType::GrayscaleAlpha) {
+                return Err(PNGParseError::ParseError("PLTE chunk in a grayscale image."));
+            }
+            if chunk.data.is_empty() || !chunk.data.len().is_multiple_of(3) || chunk.data.len() > 256 * 3 {
+                return Err(PNGParseError::ParseError("Invalid PLTE chunk length."));
+            }
+            for rgb_bytes in chunk.data.chunks_exact(3) {
+                let rgb_entry = Rgb {
+                    r: rgb_bytes[0],
+                    g: rgb_bytes[1],
+                    b: rgb_bytes[2],
+                };
+                self.pallette.push(rgb_entry);
+            }
+        } else if &chunk.type_ == b"tRNS" {
+            self.transparency = Some(self.read_transparency(&chunk.data)?);
+        } else if &chunk.type_ == b"gAMA" {
+            let gamma: [u8; 4] = chunk
+                .data
+                .as_slice()
+                .try_into()
+                .map_err(|_| PNGParseError::ParseError("Invalid gAMA chunk."))?;
+            self.gamma = Some(u32::from_be_bytes(gamma) as f64 / 100000.0);
+        } else if &chunk.type_ == b"sRGB" {
+            match chunk.data[..] {
+                [intent] if intent <= 3 => self.srgb_intent = Some(intent),
+                _ => return Err(PNGParseError::ParseError("Invalid sRGB chunk.")),
+            }
+        } else if &chunk.type_ == b"tIME" {
+            let time = match chunk.data[..] {
+                [year_hi, year_lo, month, day, hour, minute, second] => TimeStamp {
+                    year: u16::from_be_bytes([year_hi, year_lo]),
+                    month,
+                    day,
+                    hour,
+                    minute,
+                    second,
+                },
+                _ => return Err(PNGParseError::ParseError("Invalid tIME chunk.")),
+            };
+            if !(1..=12).contains(&time.month)
+                || !(1..=31).contains(&time.day)
+                || time.hour > 23
+                || time.minute > 59
+                || time.second > 60
+            {
+                return Err(PNGParseError::ParseError("Invalid tIME chunk."));
+            }
+            self.modified = Some(time);
+        } else if &chunk.type_ == b"sPLT" {
+            self.suggested_palettes.push(read_suggested_palette(&chunk.data)?);
+        } else if &chunk.type_ == b"sBIT" {
+            self.significant_bits = Some(self.read_significant_bits(&chunk.data)?);
+        } else if &chunk.type_ == b"hIST" {
+            if self.pallette.is_empty() {
+                return Err(PNGParseError::ParseError("hIST chunk before PLTE."));
+            }
+            if chunk.data.len() != self.pallette.len() * 2 {
+                return Err(PNGParseError::ParseError("Invalid hIST chunk length."));
+            }
+            let frequencies = chunk
+                .data
+                .chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect();
+            self.palette_histogram = Some(frequencies);
+        } else if &chunk.type_ == b"cHRM" {
+            if chunk.data.len() != 32 {
+                return Err(PNGParseError::ParseError("Invalid cHRM chunk."));
+            }
+            let values: Vec<u32> = chunk
+                .data
+                .chunks_exact(4)
+                .map(|v| u32::from_be_bytes([v[0], v[1], v[2], v[3]]))
+                .collect();
+            self.chromaticities = Some(Chromaticities {
+                white_x: values[0],
+                white_y: values[1],
+                red_x: values[2],
+                red_y: values[3],
+                green_x: values[4],
+                green_y: values[5],
+                blue_x: values[6],
+                blue_y: values[7],
+            });
+        } else if &chunk.type_ == b"iCCP" {
+            let (name, rest) = split_at_null(&chunk.data)
+                .ok_or(PNGParseError::ParseError("Invalid iCCP chunk, missing separator."))?;
+            match rest.split_first() {
+                Some((0, compressed)) => {
+                    // a profile that doesn't decompress is left out of
+                    // `icc_profile`, one that decompresses too far is an error
+                    let profile = match inflate_bounded(compressed, metadata_limit) {
+                        Ok(profile) => Some(profile),
+                        Err(InflateError::TooLarge) => {
+                            return Err(PNGParseError::ParseError("Chunk data exceeds the metadata size limit."))
+                        }
+                        Err(InflateError::Invalid | InflateError::Truncated) => None,
+                    };
+                    self.icc_profile = Some((latin1_to_string(name), profile))
+                }
+                Some(_) => {
+                    return Err(PNGParseError::ParseError("Unknown iCCP compression method."))
+                }
+                None => return Err(PNGParseError::ParseError("Invalid iCCP chunk.")),
+            }
+        } else if &chunk.type_ == b"tEXt" {
+            let (keyword, text) = split_at_null(&chunk.data)
+                .ok_or(PNGParseError::ParseError("Invalid tEXt chunk, missing separator."))?;
+            self.text.push(TextEntry {
+                keyword: latin1_to_string(keyword),
+                text: latin1_to_string(text),
+                compressed: false,
+            });
+        } else if &chunk.type_ == b"zTXt" {
+            let (keyword, rest) = split_at_null(&chunk.data)
+                .ok_or(PNGParseError::ParseError("Invalid zTXt chunk, missing separator."))?;
+            match rest.split_first() {
+                Some((0, compressed)) => self.text.push(TextEntry {
+                    keyword: latin1_to_string(keyword),
+                    text: latin1_to_string(&inflate(compressed, metadata_limit)?),
+                    compressed: true,
+                }),
+                Some(_) => {
+                    return Err(PNGParseError::ParseError("Unknown zTXt compression method."))
+                }
+                None => return Err(PNGParseError::ParseError("Invalid zTXt chunk.")),
+            }
+        } else if &chunk.type_ == b"iTXt" {
+            let entry = read_international_text(&chunk.data, metadata_limit)?;
+            self.international_text.push(entry);
+        } else if &chunk.type_ == b"pHYs" {
+            if chunk.data.len() != 9 {
+                return Err(PNGParseError::ParseError("Invalid pHYs chunk."));
+            }
+            let x = u32::from_be_bytes([chunk.data[0], chunk.data[1], chunk.data[2], chunk.data[3]]);
+            let y = u32::from_be_bytes([chunk.data[4], chunk.data[5], chunk.data[6], chunk.data[7]]);
+            self.physical = Some((x, y, chunk.data[8]));
+        } else if &chunk.type_ == b"bKGD" {
+            self.background = Some(self.read_background(&chunk.data)?);
+        } else if &chunk.type_ == b"acTL" {
+            if self.animation.is_some() {
+                return Err(PNGParseError::ParseError("Duplicate acTL chunk."));
+            }
+            self.animation = Some(Apng::from_animation_control(&chunk.data, self.width, self.height)?);
+        } else if &chunk.type_ == b"fcTL" {
+            let frame = Frame::from_frame_control(&chunk.data, self.width, self.height)?;
+            let animation = self
+                .animation
+                .as_mut()
+                .ok_or(PNGParseError::ParseError("fcTL chunk without acTL."))?;
+            if state.stage == ChunkStage::Header {
+                if frame.width != self.width || frame.height != self.height || frame.x_offset != 0 || frame.y_offset != 0 {
+                    return Err(PNGParseError::ParseError("Default image frame must cover the image."));
+                }
+                animation.default_image_is_first_frame = true;
+            }
+            animation.frames.push(frame);
+            state.frame_data.push(vec![]);
+        } else if &chunk.type_ == b"fdAT" {
+            let data = state.frame_data
+                .last_mut()
+                .ok_or(PNGParseError::ParseError("fdAT chunk without fcTL."))?;
+            // skip the sequence number
+            data.extend_from_slice(
+                chunk
+                    .data
+                    .get(4..)
+                    .ok_or(PNGParseError::ParseError("Invalid fdAT chunk."))?,
+            );
+        } else if &chunk.type_ == b"IEND" {
+            // IHDR straight to IEND, or only zero-length IDAT chunks
+            if state.data_chunks.is_empty() {
+                return Err(PNGParseError::ParseError("No image data before IEND."));
+            }
+            let limit = decoder
+                .max_decompressed_size
+                .unwrap_or_else(|| self.expected_data_len());
+            self.inflate_image_data(&state.data_chunks, limit)?;
+        } else {
+            self.chunks.push(chunk);
+        }
+        Ok(())
+    }
+
+    /// Inflate the concatenated IDAT (or fdAT) data into `self.data`,
+    /// failing when it inflates to more than `limit` bytes or to a size that
+    /// doesn't match the image dimensions.
+    fn inflate_image_data(&mut self, compressed: &[u8], limit: usize) -> Result<(), PNGParseError> {
+        let mut deflated_data = inflate_bounded(compressed, limit).map_err(|e| match e {
+            InflateError::Invalid => PNGParseError::ParseError("Invalid zlib stream in image data."),
+            InflateError::Truncated => PNGParseError::ParseError("Truncated zlib stream in image data."),
+            InflateError::TooLarge => PNGParseError::ParseError("Image data exceeds the decompressed size limit."),
+        })?;
+        if deflated_data.is_empty() {
+            return Err(PNGParseError::ParseError("Image data inflates to nothing."));
+        }
+        self.data.append(&mut deflated_data);
+        if self.data.len() != self.expected_data_len() {
+            return Err(PNGParseError::ParseError(
+                "Image data size doesn't match the image dimensions.",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Decode the compressed data of an animation frame to RGBA8. Frames
+    /// share the color type, bit depth, palette and transparency of the
+    /// default image.
+    fn decode_frame(
+        &self,
+        width: u32,
+        height: u32,
+        compressed: &[u8],
+        decoder: &Decoder,
+    ) -> Result<Vec<u8>, PNGParseError> {
+        let mut frame = PNGFile {
+            width,
+            height,
+            bit_depth: self.bit_depth,
+            color_type: self.color_type,
+            interlace_method: self.interlace_method,
+            pallette: self.pallette.clone(),
+            transparency: self.transparency.clone(),
+            ..Default::default()
+        };
+        let limit = decoder
+            .max_decompressed_size
+            .unwrap_or_else(|| frame.expected_data_len());
+        frame.inflate_image_data(compressed, limit)?;
+        frame.image_data = frame.defilter()?;
+        frame.check_palette_indices(decoder.clamp_palette_indices)?;
+        frame.into_rgba8()
+    }
+
+    /// Make sure every palette index of a paletted image has a PLTE entry,
+    /// either failing or, with `clamp`, replacing the ones out of range by
+    /// the last entry.
+    fn check_palette_indices(&mut self, clamp: bool) -> Result<(), PNGParseError> {
+        if self.color_type != ColorType::Palette {
+            return Ok(());
+        }
+        let len = self.pallette.len();
+        if clamp && len > 0 {
+            let last = (len - 1) as u8;
+            for index in self.image_data.iter_mut().filter(|index| **index as usize >= len) {
+                *index = last;
+            }
+        } else if self.image_data.iter().any(|index| *index as usize >= len) {
+            return Err(PNGParseError::ParseError("Palette index out of range."));
+        }
+        Ok(())
+    }
+
+    /// Check the IHDR fields describe a legal image: non-zero dimensions
+    /// and a bit depth allowed for the color type.
+    pub(crate) fn validate_header(&self) -> Result<(), PNGParseError> {
+        if self.width == 0 || self.height == 0 {
+            return Err(PNGParseError::ParseError("Invalid image dimensions."));
+        }
+        let allowed_depths: &[u8] = match self.color_type {
+            ColorType::Grayscale => &[1, 2, 4, 8, 16],
+            ColorType::Palette => &[1, 2, 4, 8],
+            ColorType::Rgb | ColorType::GrayscaleAlpha | ColorType::Rgba => &[8, 16],
+        };
+        if !allowed_depths.contains(&self.bit_depth) {
+            return Err(PNGParseError::ParseError("Invalid bit depth for color type."));
+        }
+        // every buffer size is derived from the dimensions, once this bound
+        // fits the arithmetic on them can't overflow
+        if self.max_buffer_len().is_none() {
+            return Err(PNGParseError::ParseError("Image dimensions are too large."));
+        }
+        // deflate and adaptive filtering are the only methods PNG defines
+        if self.compression_method != 0 {
+            return Err(PNGParseError::ParseError("Unknown compression method."));
+        }
+        if self.filter_method != 0 {
+            return Err(PNGParseError::ParseError("Unknown filter method."));
+        }
+        if self.interlace_method > 1 {
+            return Err(PNGParseError::ParseError("Unknown interlace method."));
+        }
+        Ok(())
+    }
+
+    /// Decode the tRNS chunk payload, its layout depends on the color type.
+    fn read_transparency(&self, data: &[u8]) -> Result<Transparency, PNGParseError> {
+        let sample = |i: usize| u16::from_be_bytes([data[i], data[i + 1]]);
+        match (self.color_type, data.len()) {
+            (ColorType::Palette, len) if len <= self.pallette.len() => Ok(Transparency::Palette(data.to_vec())),
+            (ColorType::Grayscale, 2) => Ok(Transparency::Gray(sample(0))),
+            (ColorType::Rgb, 6) => Ok(Transparency::Rgb(sample(0), sample(2), sample(4))),
+            _ => Err(PNGParseError::ParseError("Invalid tRNS chunk.")),
+        }
+    }
+
+    /// Decode the sBIT chunk payload, one value per channel (three for
+    /// paletted images), each between 1 and the sample depth.
+    fn read_significant_bits(&self, data: &[u8]) -> Result<Vec<u8>, PNGParseError> {
+        let (channels, max_bits) = match self.color_type {
+            ColorType::Palette => (3, 8),
+            color_type => (color_type.channels() as usize, self.bit_depth),
+        };
+        if data.len() != channels || data.iter().any(|bits| *bits == 0 || *bits > max_bits) {
+            return Err(PNGParseError::ParseError("Invalid sBIT chunk."));
+        }
+        Ok(data.to_vec())
+    }
+
+    /// Decode the bKGD chunk payload: a palette index, a gray level or an RGB
+    /// triple depending on the color type.
+    fn read_background(&self, data: &[u8]) -> Result<Rgb16, PNGParseError> {
+        let sample = |i: usize| self.sample_to_u16(u16::from_be_bytes([data[i], data[i + 1]]));
+        match (self.color_type, data.len()) {
+            (ColorType::Palette, 1) => {
+                let entry = self
+                    .pallette
+                    .get(data[0] as usize)
+                    .ok_or(PNGParseError::ParseError("Invalid bKGD palette index."))?;
+                Ok(Rgb16 {
+                    r: entry.r as u16 * 257,
+                    g: entry.g as u16 * 257,
+                    b: entry.b as u16 * 257,
+                })
+            }
+            (ColorType::Grayscale | ColorType::GrayscaleAlpha, 2) => Ok(Rgb16 {
+                r: sample(0),
+                g: sample(0),
+                b: sample(0),
+            }),
+            (ColorType::Rgb | ColorType::Rgba, 6) => Ok(Rgb16 {
+                r: sample(0),
+                g: sample(2),
+                b: sample(4),
+            }),
+            _ => Err(PNGParseError::ParseError("Invalid bKGD chunk.")),
+        }
+    }
+
+    /// Number of bits a single pixel takes in a scanline, derived from the
+    /// color type and the bit depth.
+    fn bits_per_pixel(&self) -> usize {
+        self.channels() as usize * self.bit_depth as usize
+    }
+
+    /// Read the raw value of sample `channel` of the pixel at (x, y) from the
+    /// defiltered data.
+    fn sample(&self, x: u32, y: u32, channel: usize) -> Option<u16> {
+        let index = (y as usize * self.width as usize + x as usize) * self.channels() as usize + channel;
+        if self.bit_depth == 16 {
+            let bytes = self.image_data.get(index * 2..index * 2 + 2)?;
+            Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+        } else {
+            self.image_data.get(index).map(|b| *b as u16)
+        }
+    }
+
+    /// Scale a raw sample value to the 0-65535 range.
+    fn sample_to_u16(&self, value: u16) -> u16 {
+        match self.bit_depth {
+            16 => value,
+            depth => (value as u32 * 65535 / ((1 << depth) - 1)) as u16,
+        }
+    }
+
+    /// Get the RGBA value of the pixel at (x, y), or `None` when the
+    /// coordinates are out of bounds. Grayscale samples are replicated across
+    /// R, G and B and palette indices are looked up in the palette. Alpha
+    /// comes from the alpha channel or the tRNS chunk, if any.
+    /// 16-bit samples are downscaled to their most significant byte.
+    pub fn get_pixel(&self, x: u32, y: u32) -> Option<[u8; 4]> {
+        self.get_pixel16(x, y).map(|pixel| pixel.map(|v| (v >> 8) as u8))
+    }
+
+    /// Same as `get_pixel` but with 16 bits per channel. 16-bit samples are
+    /// returned as stored, lower bit depths are scaled up to the full range.
+    pub fn get_pixel16(&self, x: u32, y: u32) -> Option<[u16; 4]> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        let raw = |channel| self.sample(x, y, channel);
+        let sample = |channel| raw(channel).map(|v| self.sample_to_u16(v));
+        match self.color_type {
+            ColorType::Grayscale => {
+                let luma = sample(0)?;
+                let alpha = match self.transparency {
+                    Some(Transparency::Gray(gray)) if raw(0)? == gray => 0,
+                    _ => 65535,
+                };
+                Some([luma, luma, luma, alpha])
+            }
+            ColorType::Rgb => {
+                let alpha = match self.transparency {
+                    Some(Transparency::Rgb(r, g, b)) if (raw(0)?, raw(1)?, raw(2)?) == (r, g, b) => 0,
+                    _ => 65535,
+                };
+                Some([sample(0)?, sample(1)?, sample(2)?, alpha])
+            }
+            ColorType::Palette => {
+                let index = raw(0)? as usize;
+                let entry = self.pallette.get(index)?;
+                let alpha = match &self.transparency {
+                    Some(Transparency::Palette(alphas)) => *alphas.get(index).unwrap_or(&255),
+                    _ => 255,
+                };
+                Some([entry.r, entry.g, entry.b, alpha].map(|v| v as u16 * 257))
+            }
+            ColorType::GrayscaleAlpha => {
+                let luma = sample(0)?;
+                Some([luma, luma, luma, sample(1)?])
+            }
+            ColorType::Rgba => Some([sample(0)?, sample(1)?, sample(2)?, sample(3)?]),
+        }
+    }
+
+    /// Upper bound for the size of any buffer the image needs: the filtered
+    /// data, with up to 15 bytes of filter types and padding per row over
+    /// the Adam7 passes, or the decoded pixels at up to 8 bytes each. `None`
+    /// when it doesn't fit in `usize`.
+    fn max_buffer_len(&self) -> Option<usize> {
+        let width = self.width as usize;
+        let height = self.height as usize;
+        let filtered = width
+            .checked_mul(self.bits_per_pixel())?
+            .div_ceil(8)
+            .checked_add(15)?
+            .checked_mul(height)?;
+        let decoded = width.checked_mul(height)?.checked_mul(8)?;
+        Some(filtered.max(decoded))
+    }
+
+    /// Size of the inflated image data implied by IHDR: every scanline with
+    /// its filter-type byte, for each of the Adam7 passes when interlaced.
+    fn expected_data_len(&self) -> usize {
+        let bits_per_pixel = self.bits_per_pixel();
+        let scanlines_len = |width: usize, height: usize| {
+            if width == 0 {
+                0
+            } else {
+                height * (1 + (width * bits_per_pixel).div_ceil(8))
+            }
+        };
+        let width = self.width as usize;
+        let height = self.height as usize;
+        if self.is_interlaced() {
+            ADAM7_PASSES
+                .iter()
+                .map(|&(x0, y0, dx, dy)| {
+                    scanlines_len(
+                        width.saturating_sub(x0).div_ceil(dx),
+                        height.saturating_sub(y0).div_ceil(dy),
+                    )
+                })
+                .sum()
+        } else {
+            scanlines_len(width, height)
+        }
+    }
+
+    /// Reverse the per-scanline filters of the inflated image data.
+    /// Returns the reconstructed scanlines back to back, without their
+    /// leading filter-type bytes. Samples smaller than a byte are unpacked
+    /// to one byte each and Adam7 interlaced images are reassembled into
+    /// their progressive layout.
+    pub fn defilter(&mut self) -> Result<Vec<u8>, PNGParseError> {
+        let width = self.width as usize;
+        let height = self.height as usize;
+        match self.interlace_method {
+            0 => Ok(self.defilter_pass(0, width, height, 0)?.0),
+            1 => {
+                let pixel_len = self.channels() as usize * if self.bit_depth == 16 { 2 } else { 1 };
+                let mut image_data = vec![0u8; width * height * pixel_len];
+                let mut offset = 0;
+                for (x0, y0, dx, dy) in ADAM7_PASSES {
+                    let pass_width = width.saturating_sub(x0).div_ceil(dx);
+                    let pass_height = height.saturating_sub(y0).div_ceil(dy);
+                    // passes without any pixels have no scanlines at all
+                    if pass_width == 0 || pass_height == 0 {
+                        continue;
+                    }
+                    let (pass, consumed) = self.defilter_pass(offset, pass_width, pass_height, 0)?;
+                    offset += consumed;
+                    for (i, pixel) in pass.chunks_exact(pixel_len).enumerate() {
+                        let x = x0 + (i % pass_width) * dx;
+                        let y = y0 + (i / pass_width) * dy;
+                        let start = (y * width + x) * pixel_len;
+                        image_data[start..start + pixel_len].copy_from_slice(pixel);
+                    }
+                }
+                Ok(image_data)
+            }
+            _ => Err(PNGParseError::ParseError("Unknown interlace method.")),
+        }
+    }
+
+    /// Defilter the `width` x `height` (sub-)image whose scanlines start at
+    /// `offset` in the inflated data. Returns the samples and the number of
+    /// inflated bytes consumed. The first `skip` rows are defiltered, since
+    /// later rows depend on them, but left out of the samples.
+    fn defilter_pass(
+        &self,
+        offset: usize,
+        width: usize,
+        height: usize,
+        skip: usize,
+    ) -> Result<(Vec<u8>, usize), PNGParseError> {
+        let bits_per_pixel = self.bits_per_pixel();
+        let bpp = self.bytes_per_pixel();
+        let row_len = (width * bits_per_pixel).div_ceil(8);
+        let len = height * (row_len + 1);
+        let scanlines = self
+            .data
+            .get(offset..offset + len)
+            .ok_or(PNGParseError::ParseError("Image data is too short."))?;
+        let mut image_data: Vec<u8> = Vec::with_capacity(row_len * height.saturating_sub(skip));
+        let mut keep = |row: &[u8]| {
+            if self.bit_depth < 8 {
+                let count = width * self.channels() as usize;
+                image_data.extend(unpack_samples(row, self.bit_depth, count));
+            } else {
+                image_data.extend_from_slice(row);
+            }
+        };
+        if let Some(rows) = unfilter_up_parallel(scanlines, row_len) {
+            rows.chunks(row_len).skip(skip).for_each(keep);
+        } else {
+            let mut previous = vec![0u8; row_len];
+            for (y, scanline) in scanlines.chunks(row_len + 1).enumerate() {
+                let mut current = scanline[1..].to_vec();
+                unfilter_scanline(scanline[0], bpp, &previous, &mut current)?;
+                if y >= skip {
+                    keep(&current);
+                }
+                previous = current;
+            }
+        }
+        Ok((image_data, len))
+    }
+
+    /// Decode rows `start..end` to RGBA8 like `into_rgba8`, straight from
+    /// the filtered data. Rows above `start` still have to be defiltered but
+    /// only the requested rows are kept. Interlaced images spread every row
+    /// over all passes, for them the whole image is decoded.
+    pub fn decode_rows(&self, start: u32, end: u32) -> Result<Vec<u8>, PNGParseError> {
+        if start > end || end > self.height {
+            return Err(PNGParseError::ParseError("Row range is outside the image."));
+        }
+        if start == end {
+            return Ok(vec![]);
+        }
+        if self.interlace_method != 0 {
+            let row_len = self.width as usize * 4;
+            let rgba = self.into_rgba8()?;
+            return Ok(rgba[start as usize * row_len..end as usize * row_len].to_vec());
+        }
+        let (samples, _) = self.defilter_pass(0, self.width as usize, end as usize, start as usize)?;
+        let mut rows = PNGFile {
+            width: self.width,
+            height: end - start,
+            bit_depth: self.bit_depth,
+            color_type: self.color_type,
+            pallette: self.pallette.clone(),
+            transparency: self.transparency.clone(),
+            image_data: samples,
+            ..Default::default()
+        };
+        // the file decoded, so indices out of range can only be left when
+        // `Decoder::clamp_palette_indices` allowed them
+        rows.check_palette_indices(true)?;
+        rows.into_rgba8()
+    }
+
+    /// Decoded pixels as a flat, row-major RGBA buffer with 8 bits per
+    /// channel, `width * height * 4` bytes long. Every color type and bit
+    /// depth is expanded the same way `get_pixel` does.
+    pub fn into_rgba8(&self) -> Result<Vec<u8>, PNGParseError> {
+        if self.image_data.is_empty() {
+            return Err(PNGParseError::ParseError("No decoded image data."));
+        }
+        let mut rgba: Vec<u8> = Vec::with_capacity(self.width as usize * self.height as usize * 4);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let pixel = self
+                    .get_pixel(x, y)
+                    .ok_or(PNGParseError::ParseError("Invalid pixel data."))?;
+                rgba.extend_from_slice(&pixel);
+            }
+        }
+        Ok(rgba)
+    }
+
+    /// Like `into_rgba8` with each color channel multiplied by alpha / 255,
+    /// rounded to the nearest integer (255 is odd, so there are no ties).
+    /// Fully opaque pixels are unchanged, fully transparent ones become all
+    /// zero.
+    pub fn into_rgba8_premultiplied(&self) -> Result<Vec<u8>, PNGParseError> {
+        let mut rgba = self.into_rgba8()?;
+        for pixel in rgba.chunks_exact_mut(4) {
+            let alpha = pixel[3] as u32;
+            for channel in &mut pixel[..3] {
+                *channel = ((*channel as u32 * alpha + 127) / 255) as u8;
+            }
+        }
+        Ok(rgba)
+    }
+
+    /// The defiltered rows of raw samples, without palette or grayscale
+    /// expansion. Each row is `width * channels` samples of one byte, or two
+    /// big-endian bytes at a bit depth of 16; samples smaller than a byte
+    /// are unpacked to one byte each.
+    pub fn scanlines(&self) -> Result<Vec<&[u8]>, PNGParseError> {
+        if self.image_data.is_empty() {
+            return Err(PNGParseError::ParseError("No decoded image data."));
+        }
+        let sample_len = if self.bit_depth == 16 { 2 } else { 1 };
+        let row_len = self.width as usize * self.channels() as usize * sample_len;
+        Ok(self.image_data.chunks_exact(row_len).collect())
+    }
+
+    /// Iterate over the decoded pixels as `(x, y, [r, g, b, a])` tuples in
+    /// row-major order: left to right within a row, rows from top to bottom.
+    /// Samples are expanded to RGBA lazily, one pixel per call to `next`.
+    /// Yields nothing if the image holds no decoded data.
+    pub fn pixels(&self) -> Pixels<'_> {
+        let len = if self.image_data.is_empty() {
+            0
+        } else {
+            self.width as usize * self.height as usize
+        };
+        Pixels {
+            image: self,
+            index: 0,
+            len,
+        }
+    }
+
+    /// Encode the image as a PNG stream with the default `Encoder` settings.
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<(), PNGParseError> {
+        Encoder::new().encode(self, writer)
+    }
+
+    /// Write the image as a PNG file at `path`, replacing any existing file,
+    /// the counterpart of `open`.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), PNGParseError> {
+        let mut writer = std::io::BufWriter::new(File::create(path)?);
+        self.write(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Write the decoded pixels as a binary PPM (P6) image, or as a PAM (P7)
+    /// image with an RGB_ALPHA tuple type if the image has transparency.
+    /// Samples are 8-bit, as in `into_rgba8`.
+    pub fn write_ppm<W: Write>(&self, writer: &mut W) -> Result<(), PNGParseError> {
+        let rgba = self.into_rgba8()?;
+        if self.has_alpha() {
+            write!(
+                writer,
+                "P7\nWIDTH {}\nHEIGHT {}\nDEPTH 4\nMAXVAL 255\nTUPLTYPE RGB_ALPHA\nENDHDR\n",
+                self.width, self.height
+            )?;
+            writer.write_all(&rgba)?;
+        } else {
+            write!(writer, "P6\n{} {}\n255\n", self.width, self.height)?;
+            let rgb: Vec<u8> = rgba.chunks_exact(4).flat_map(|pixel| &pixel[..3]).copied().collect();
+            writer.write_all(&rgb)?;
+        }
+        Ok(())
+    }
+
+    fn write_with<W: Write>(&self, writer: &mut W, encoder: &Encoder) -> Result<(), PNGParseError> {
+        writer.write_all(&PNG_SIGNATURE)?;
+
+        let mut ihdr: Vec<u8> = Vec::with_capacity(13);
+        ihdr.extend_from_slice(&self.width.to_be_bytes());
+        ihdr.extend_from_slice(&self.height.to_be_bytes());
+        ihdr.extend_from_slice(&[self.bit_depth, self.color_type as u8, 0, 0, 0]);
+        write_chunk(writer, b"IHDR", &ihdr)?;
+
+        if self.color_type == ColorType::Palette {
+            let plte: Vec<u8> = self.pallette.iter().flat_map(|e| [e.r, e.g, e.b]).collect();
+            write_chunk(writer, b"PLTE", &plte)?;
+        }
+
+        if let Some(transparency) = &self.transparency {
+            let trns: Vec<u8> = match transparency {
+                Transparency::Palette(alphas) => alphas.clone(),
+                Transparency::Gray(gray) => gray.to_be_bytes().to_vec(),
+                Transparency::Rgb(r, g, b) => [r, g, b].iter().flat_map(|v| v.to_be_bytes()).collect(),
+            };
+            write_chunk(writer, b"tRNS", &trns)?;
+        }
+
+        if encoder.copy_safe_chunks {
+            self.write_safe_chunks(writer)?;
+        }
+
+        write_chunk(writer, b"IDAT", &deflate(&self.filtered_scanlines(encoder.filter)?)?)?;
+
+        write_chunk(writer, b"IEND", &[])?;
+        Ok(())
+    }
+
+    /// Write the ancillary chunks that are safe to copy: the parsed pHYs,
+    /// sPLT, tEXt, zTXt and iTXt chunks rebuilt from their fields, then the
+    /// unknown ones as they were read.
+    fn write_safe_chunks<W: Write>(&self, writer: &mut W) -> Result<(), PNGParseError> {
+        if let Some((x, y, unit)) = self.physical {
+            let mut data = x.to_be_bytes().to_vec();
+            data.extend_from_slice(&y.to_be_bytes());
+            data.push(unit);
+            write_chunk(writer, b"pHYs", &data)?;
+        }
+        for palette in &self.suggested_palettes {
+            let mut data = string_to_latin1(&palette.name);
+            data.extend([0, palette.sample_depth]);
+            for entry in &palette.entries {
+                for sample in [entry.red, entry.green, entry.blue, entry.alpha] {
+                    if palette.sample_depth == 8 {
+                        data.push(sample as u8);
+                    } else {
+                        data.extend_from_slice(&sample.to_be_bytes());
+                    }
+                }
+                data.extend_from_slice(&entry.frequency.to_be_bytes());
+            }
+            write_chunk(writer, b"sPLT", &data)?;
+        }
+        for entry in &self.text {
+            let mut data = string_to_latin1(&entry.keyword);
+            data.push(0);
+            if entry.compressed {
+                data.push(0);
+                data.extend(deflate(&string_to_latin1(&entry.text))?);
+                write_chunk(writer, b"zTXt", &data)?;
+            } else {
+                data.extend(string_to_latin1(&entry.text));
+                write_chunk(writer, b"tEXt", &data)?;
+            }
+        }
+        for entry in &self.international_text {
+            let mut data = string_to_latin1(&entry.keyword);
+            data.extend([0, entry.compressed as u8, 0]);
+            data.extend_from_slice(entry.language.as_bytes());
+            data.push(0);
+            data.extend_from_slice(entry.translated_keyword.as_bytes());
+            data.push(0);
+            if entry.compressed {
+                data.extend(deflate(entry.text.as_bytes())?);
+            } else {
+                data.extend_from_slice(entry.text.as_bytes());
+            }
+            write_chunk(writer, b"iTXt", &data)?;
+        }
+        for chunk in self.chunks.iter().filter(|chunk| chunk.is_ancillary() && chunk.is_safe_to_copy()) {
+            write_chunk(writer, &chunk.type_, &chunk.data)?;
+        }
+        Ok(())
+    }
+
+    /// Pack the decoded samples back into scanlines and filter them, each
+    /// prefixed with the filter type `strategy` picked for it.
+    fn filtered_scanlines(&self, strategy: FilterStrategy) -> Result<Vec<u8>, PNGParseError> {
+        let sample_len = if self.bit_depth == 16 { 2 } else { 1 };
+        let row_len = self.width as usize * self.channels() as usize * sample_len;
+        // e.g. a `read_info` result, which has no pixels to encode
+        if row_len == 0 || self.image_data.len() != row_len * self.height as usize {
+            return Err(PNGParseError::ParseError(
+                "Decoded image data doesn't match the image dimensions.",
+            ));
+        }
+        let mut scanlines: Vec<u8> = Vec::new();
+        let bpp = self.bytes_per_pixel();
+        let mut previous: Vec<u8> = vec![];
+        for row in self.image_data.chunks_exact(row_len) {
+            let raw = if self.bit_depth < 8 {
+                pack_samples(row, self.bit_depth)
+            } else {
+                row.to_vec()
+            };
+            if previous.is_empty() {
+                previous = vec![0; raw.len()];
+            }
+            let (filter_type, filtered) = match strategy {
+                FilterStrategy::None => (0, raw.clone()),
+                FilterStrategy::Fixed(filter_type) if filter_type <= 4 => {
+                    (filter_type, filter_scanline(filter_type, bpp, &previous, &raw))
+                }
+                FilterStrategy::Fixed(filter_type) => return Err(PNGParseError::UnknownFilter(filter_type)),
+                FilterStrategy::MinSumOfAbsolute => (0..=4)
+                    .map(|filter_type| (filter_type, filter_scanline(filter_type, bpp, &previous, &raw)))
+                    .min_by_key(|(_, filtered)| {
+                        filtered.iter().map(|b| (*b as i8).unsigned_abs() as u64).sum::<u64>()
+                    })
+                    .unwrap_or_default(),
+            };
+            scanlines.push(filter_type);
+            scanlines.extend_from_slice(&filtered);
+            previous = raw;
+        }
+        Ok(scanlines)
+    }
+
+    /// Read the remaining chunks up to IEND and check their CRCs in
+    /// parallel, pairing each chunk with whether its CRC is valid.
+    fn read_chunks_verified(
+        reader: &mut dyn Read,
+        max_chunks: usize,
+    ) -> Result<Vec<(Chunk, bool)>, PNGParseError> {
+        let mut chunks = vec![];
+        let mut limited_chunks = 0;
+        let mut offset = PNG_SIGNATURE.len() as u64;
+        while let Some(chunk) = PNGFile::read_chunk(reader, offset)? {
+            offset += 12 + chunk.size as u64;
+            if counts_toward_max_chunks(&chunk.type_) {
+                limited_chunks += 1;
+                if limited_chunks > max_chunks {
+                    return Err(PNGParseError::ParseError("Too many chunks."));
+                }
+            }
+            let is_end = &chunk.type_ == b"IEND";
+            chunks.push(chunk);
+            if is_end {
+                break;
+            }
+        }
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        Ok(verify_crcs(chunks, threads))
+    }
+
+    /// Read the next chunk, starting at byte `offset` of the stream. `None`
+    /// when the stream ends cleanly before the chunk starts,
+    /// `PNGParseError::EOF` when it ends inside the chunk. Errors carry the
+    /// chunk type, `????` when the stream ends before it.
+    fn read_chunk<R: Read + ?Sized>(reader: &mut R, offset: u64) -> Result<Option<Chunk>, PNGParseError> {
+        let mut chunk_type = *b"????";
+        PNGFile::read_chunk_fields(reader, &mut chunk_type).map_err(|e| e.in_chunk(&chunk_type, offset))
+    }
+
+    fn read_chunk_fields<R: Read + ?Sized>(
+        reader: &mut R,
+        chunk_type: &mut [u8; 4],
+    ) -> Result<Option<Chunk>, PNGParseError> {
+        let mut chunk_st: [u8; 4] = [0; 4];
+        let mut bytes_read = read_full(reader, &mut chunk_st)?;
+        if bytes_read == 0 {
+            return Ok(None);
+        }
+        if bytes_read != chunk_st.len() {
+            return Err(PNGParseError::EOF);
+        }
+        let chunk_size_int = u32::from_be_bytes(chunk_st);
+        let mut chunk_type_buf = [0; 4];
+        bytes_read = read_full(reader, &mut chunk_type_buf)?;
+        if bytes_read != chunk_st.len() {
+            return Err(PNGParseError::EOF);
+        }
+        *chunk_type = chunk_type_buf;
+        if chunk_size_int > MAX_CHUNK_LEN {
+            return Err(PNGParseError::ParseError("Chunk length exceeds 2^31-1 bytes."));
+        }
+        // grow the buffer as data arrives instead of trusting the length
+        // field, so a truncated file can't claim a 2 GiB allocation
+        let mut chunk_data: Vec<u8> = vec![];
+        bytes_read = (&mut *reader).take(chunk_size_int as u64).read_to_end(&mut chunk_data)?;
+        if bytes_read != chunk_size_int as usize {
+            return Err(PNGParseError::EOF);
+        }
+
+        let mut chunk_crc_buf = [0; 4];
+        bytes_read = read_full(reader, &mut chunk_crc_buf)?;
+        if bytes_read != chunk_st.len() {
+            return Err(PNGParseError::EOF);
+        }
+        let chunk_crc = u32::from_be_bytes(chunk_crc_buf);
+
+        let chunk = Chunk {
+            type_: chunk_type_buf,
+            size: chunk_size_int,
+            data: chunk_data,
+            crc: chunk_crc,
+        };
+        Ok(Some(chunk))
+    }
+
+}
+
+/// One line summary, e.g. `PNG 640x480 8-bit RGBA, 3 ancillary chunks`.
+impl std::fmt::Display for PNGFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "PNG {}x{} {}-bit {}", self.width, self.height, self.bit_depth, self.color_type_name())?;
+        if self.is_interlaced() {
+            write!(f, " interlaced")?;
+        }
+        let plural = if self.ancillary_count == 1 { "" } else { "s" };
+        write!(f, ", {} ancillary chunk{}", self.ancillary_count, plural)
+    }
+}
+
+/// Header properties and metadata counts, the pixel data is left out.
+impl std::fmt::Debug for PNGFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PNGFile")
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .field("bit_depth", &self.bit_depth)
+            .field("color_type", &self.color_type)
+            .field("interlace_method", &self.interlace_method)
+            .field("palette_len", &self.pallette.len())
+            .field("transparency", &self.transparency)
+            .field("gamma", &self.gamma)
+            .field("text", &self.text)
+            .field("international_text", &self.international_text)
+            .field("chunks", &self.chunks.len())
+            .field("animation_frames", &self.animation.as_ref().map(|a| a.frames().len()))
+            .finish_non_exhaustive()
+    }
+}
+
+/// The decoded pixels as an `image` crate RGBA8 buffer, available with the
+/// `image` feature. Fails like `into_rgba8` when there's no pixel data.
+#[cfg(feature = "image")]
+impl TryFrom<PNGFile> for image::DynamicImage {
+    type Error = PNGParseError;
+
+    fn try_from(png: PNGFile) -> Result<image::DynamicImage, PNGParseError> {
+        let buffer = image::RgbaImage::from_raw(png.width, png.height, png.into_rgba8()?)
+            .ok_or(PNGParseError::ParseError("Invalid pixel data."))?;
+        Ok(image::DynamicImage::ImageRgba8(buffer))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apng::{BlendOp, DisposeOp};
+
+    fn chunk(type_: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut bytes = (data.len() as u32).to_be_bytes().to_vec();
+        bytes.extend_from_slice(type_);
+        bytes.extend_from_slice(data);
+        let mut crc = Crc32::new();
+        crc.update(type_);
+        crc.update(data);
+        bytes.extend_from_slice(&crc.finalize().to_be_bytes());
+        bytes
+    }
+
+    fn ihdr(width: u32, height: u32, bit_depth: u8, color_type: u8, interlace: u8) -> Vec<u8> {
+        let mut data = width.to_be_bytes().to_vec();
+        data.extend_from_slice(&height.to_be_bytes());
+        data.extend_from_slice(&[bit_depth, color_type, 0, 0, interlace]);
+        chunk(b"IHDR", &data)
+    }
+
+    /// An IDAT chunk holding `scanlines`, filter-type bytes included.
+    fn idat(scanlines: &[u8]) -> Vec<u8> {
+        chunk(b"IDAT", &deflate(scanlines).unwrap())
+    }
+
+    fn iend() -> Vec<u8> {
+        chunk(b"IEND", &[])
+    }
+
+    fn png(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let mut bytes = PNG_SIGNATURE.to_vec();
+        for chunk in chunks {
+            bytes.extend_from_slice(chunk);
+        }
+        bytes
+    }
+
+    fn frame_control(sequence: u32, width: u32, height: u32) -> Vec<u8> {
+        let mut data = sequence.to_be_bytes().to_vec();
+        for value in [width, height, 0, 0] {
+            data.extend_from_slice(&value.to_be_bytes());
+        }
+        data.extend_from_slice(&[0, 1, 0, 1, 0, 0]);
+        chunk(b"fcTL", &data)
+    }
+
+    fn animation_control(num_frames: u32) -> Vec<u8> {
+        let mut data = num_frames.to_be_bytes().to_vec();
+        data.extend_from_slice(&0u32.to_be_bytes());
+        chunk(b"acTL", &data)
+    }
+
+    fn plte(entries: &[[u8; 3]]) -> Vec<u8> {
+        chunk(b"PLTE", &entries.concat())
+    }
+
+    /// A 2x2 8-bit RGB image: red, green on the first row, blue, white on
+    /// the second.
+    fn rgb_2x2() -> Vec<u8> {
+        png(&[
+            ihdr(2, 2, 8, 2, 0),
+            idat(&[0, 255, 0, 0, 0, 255, 0, 0, 0, 0, 255, 255, 255, 255]),
+            iend(),
+        ])
+    }
+
+    /// Split a chunk error into the chunk type, its offset and the error
+    /// inside it.
+    fn chunk_error(result: Result<PNGFile, PNGParseError>) -> (String, u64, PNGParseError) {
+        match result {
+            Err(PNGParseError::InChunk { chunk_type, offset, source }) => (chunk_type, offset, *source),
+            other => panic!("unexpected result {:?}", other.map(|_| ())),
+        }
+    }
+
+    /// Hands out `bytes`, then fails with `kind` instead of reaching EOF.
+    struct FailingReader {
+        bytes: Vec<u8>,
+        position: usize,
+        kind: std::io::ErrorKind,
+    }
+
+    impl Read for FailingReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let rest = &self.bytes[self.position..];
+            if rest.is_empty() {
+                return Err(std::io::Error::new(self.kind, "read failed"));
+            }
+            let len = buf.len().min(rest.len());
+            buf[..len].copy_from_slice(&rest[..len]);
+            self.position += len;
+            Ok(len)
+        }
+    }
+
+    /// The scanlines of an 8-bit grayscale image interlaced with Adam7, all
+    /// with filter type None.
+    fn adam7_scanlines(pixels: &[u8], width: usize, height: usize) -> Vec<u8> {
+        let mut scanlines = vec![];
+        for (x0, y0, dx, dy) in ADAM7_PASSES {
+            if x0 >= width || y0 >= height {
+                continue;
+            }
+            for y in (y0..height).step_by(dy) {
+                scanlines.push(0);
+                scanlines.extend((x0..width).step_by(dx).map(|x| pixels[y * width + x]));
+            }
+        }
+        scanlines
+    }
+
+    /// `chunk` with its last CRC byte flipped.
+    fn corrupt(mut chunk: Vec<u8>) -> Vec<u8> {
+        let last = chunk.len() - 1;
+        chunk[last] ^= 1;
+        chunk
+    }
+
+    /// An fdAT chunk holding the filtered `scanlines` of a frame.
+    fn frame_data(sequence: u32, scanlines: &[u8]) -> Vec<u8> {
+        let mut data = sequence.to_be_bytes().to_vec();
+        data.extend(deflate(scanlines).unwrap());
+        chunk(b"fdAT", &data)
+    }
+
+    #[test]
+    fn copy_safe_chunks_skips_unknown_critical_chunks() {
+        let bytes = png(&[
+            ihdr(1, 1, 8, 0, 0),
+            chunk(b"XXXx", b"critical"),
+            chunk(b"prIv", b"ancillary"),
+            idat(&[0, 0]),
+            iend(),
+        ]);
+        let image = PNGFile::from_bytes(&bytes).unwrap();
+        let mut written = vec![];
+        Encoder::new().copy_safe_chunks(true).encode(&image, &mut written).unwrap();
+        let types: Vec<[u8; 4]> = PNGFile::iter_chunks(&written[..]).map(|header| header.unwrap().type_).collect();
+        assert_eq!(types, [*b"IHDR", *b"prIv", *b"IDAT", *b"IEND"]);
+    }
+
+    #[test]
+    fn corrupt_ancillary_chunk_before_ihdr_is_out_of_order() {
+        let mut text = chunk(b"tEXt", b"key\0value");
+        let last = text.len() - 1;
+        text[last] ^= 1;
+        let bytes = png(&[text, ihdr(1, 1, 8, 0, 0), idat(&[0, 0]), iend()]);
+        match PNGFile::from_bytes(&bytes).unwrap_err() {
+            PNGParseError::InChunk { chunk_type, offset, source } => {
+                assert_eq!((chunk_type.as_str(), offset), ("tEXt", 8));
+                assert!(matches!(*source, PNGParseError::ParseError("IHDR must be the first chunk.")));
+            }
+            error => panic!("unexpected error {:?}", error),
+        }
+    }
+
+    #[test]
+    fn writing_an_image_without_pixels_fails() {
+        let bytes = png(&[ihdr(2, 2, 8, 2, 0), idat(&[0; 14]), iend()]);
+        let header = PNGFile::read_info(&bytes[..]).unwrap();
+        for image in [header.clone(), header.flip_vertical()] {
+            assert!(matches!(
+                image.write(&mut vec![]),
+                Err(PNGParseError::ParseError("Decoded image data doesn't match the image dimensions."))
+            ));
+        }
+        let mut written = vec![];
+        PNGFile::from_bytes(&bytes).unwrap().write(&mut written).unwrap();
+        assert!(PNGFile::from_bytes(&written).is_ok());
+    }
+
+    #[test]
+    fn read_errors_carry_the_chunk_offset() {
+        let mut truncated = png(&[ihdr(1, 1, 8, 0, 0), idat(&[0, 0]), iend()]);
+        truncated.truncate(PNG_SIGNATURE.len() + 25 + 10);
+        let mut oversized = png(&[ihdr(1, 1, 8, 0, 0)]);
+        oversized.extend_from_slice(&[0xFF, 0xFF, 0xFF, 0xFF]);
+        oversized.extend_from_slice(b"IDAT");
+        for parallel_crc in [false, true] {
+            let decoder = Decoder::new().parallel_crc(parallel_crc);
+            match decoder.decode(&truncated[..]) {
+                Err(PNGParseError::InChunk { chunk_type, offset, source }) => {
+                    assert_eq!((chunk_type.as_str(), offset), ("IDAT", 33));
+                    assert!(matches!(*source, PNGParseError::EOF));
+                }
+                other => panic!("unexpected result {:?}", other.map(|_| ())),
+            }
+            match decoder.decode(&oversized[..]) {
+                Err(PNGParseError::InChunk { chunk_type, offset, .. }) => {
+                    assert_eq!((chunk_type.as_str(), offset), ("IDAT", 33));
+                }
+                other => panic!("unexpected result {:?}", other.map(|_| ())),
+            }
+        }
+    }
+
+    #[test]
+    fn pixel_data_errors_point_at_the_first_idat() {
+        let bytes = png(&[ihdr(1, 1, 8, 0, 0), idat(&[9, 0]), iend()]);
+        match PNGFile::from_bytes(&bytes) {
+            Err(PNGParseError::InChunk { chunk_type, offset, source }) => {
+                assert_eq!((chunk_type.as_str(), offset), ("IDAT", 33));
+                assert!(matches!(*source, PNGParseError::UnknownFilter(9)));
+            }
+            other => panic!("unexpected result {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn chunks_by_type_finds_parsed_and_unparsed_chunks() {
+        let bytes = png(&[
+            ihdr(1, 1, 8, 0, 0),
+            chunk(b"tEXt", b"Title\0ruro"),
+            chunk(b"prIv", b"a"),
+            chunk(b"tEXt", b"Author\0me"),
+            chunk(b"prIv", b"b"),
+            idat(&[0, 0]),
+            iend(),
+        ]);
+        let image = PNGFile::from_bytes(&bytes).unwrap();
+        assert_eq!(image.chunk_by_type(b"tEXt").map(|chunk| chunk.data()), Some(&b"Title\0ruro"[..]));
+        assert_eq!(image.chunks_by_type(b"tEXt").count(), 2);
+        assert_eq!(image.chunk_by_type(b"IHDR").map(|chunk| chunk.size()), Some(13));
+        assert!(image.chunk_by_type(b"zTXt").is_none());
+        assert!(image.chunk_by_type(b"IDAT").is_none());
+        // the needle doesn't have to outlive the iterator
+        let private = {
+            let type_ = *b"prIv";
+            image.chunks_by_type(&type_)
+        };
+        let data: Vec<&[u8]> = private.map(|chunk| chunk.data()).collect();
+        assert_eq!(data, [b"a", b"b"]);
+    }
+
+    #[test]
+    fn parallel_up_unfiltering_matches_serial() {
+        let (row_len, height) = (1021, 1100);
+        let mut seed = 1u32;
+        let mut scanlines = vec![];
+        for y in 0..height {
+            scanlines.push(if y % 3 == 0 { 0 } else { 2 });
+            for _ in 0..row_len {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                scanlines.push((seed >> 16) as u8);
+            }
+        }
+        assert!(scanlines.len() >= PARALLEL_UNFILTER_MIN_LEN);
+        let mut serial = vec![];
+        let mut previous = vec![0u8; row_len];
+        for scanline in scanlines.chunks(row_len + 1) {
+            let mut current = scanline[1..].to_vec();
+            unfilter_scanline(scanline[0], 1, &previous, &mut current).unwrap();
+            serial.extend_from_slice(&current);
+            previous = current;
+        }
+        for threads in [2, 3, 8] {
+            assert_eq!(unfilter_up_on_threads(&scanlines, row_len, threads).as_deref(), Some(&serial[..]));
+        }
+        assert_eq!(unfilter_up_on_threads(&scanlines, row_len, 1), None);
+        scanlines[(row_len + 1) * 7] = 1;
+        assert_eq!(unfilter_up_on_threads(&scanlines, row_len, 4), None);
+    }
+
+    #[test]
+    fn parallel_crc_flags_the_same_chunks_as_serial() {
+        let chunks: Vec<Chunk> = (0..10u8)
+            .map(|i| Chunk {
+                type_: *b"prIv",
+                size: 1,
+                data: vec![i],
+                crc: if i == 6 { 0 } else { crc(&[b'p', b'r', b'I', b'v', i]) },
+            })
+            .collect();
+        let serial: Vec<bool> = chunks.iter().map(Chunk::crc_is_valid).collect();
+        assert_eq!(serial.iter().filter(|ok| !**ok).count(), 1);
+        for threads in [1, 3, 16] {
+            let parallel: Vec<bool> = verify_crcs(chunks.clone(), threads).into_iter().map(|(_, ok)| ok).collect();
+            assert_eq!(parallel, serial);
+        }
+        let mut corrupt = chunk(b"tEXt", b"Title\0ruro");
+        *corrupt.last_mut().unwrap() ^= 1;
+        let bytes = png(&[ihdr(1, 1, 8, 0, 0), corrupt, idat(&[0, 0]), iend()]);
+        let errors: Vec<Vec<String>> = [false, true]
+            .map(|parallel_crc| {
+                let image = Decoder::new().parallel_crc(parallel_crc).decode(&bytes[..]).unwrap();
+                image.crc_errors().iter().map(|e| e.to_string()).collect()
+            })
+            .into();
+        assert_eq!(errors[0], ["in `tEXt` chunk at byte 33"]);
+        assert_eq!(errors[0], errors[1]);
+    }
+
+    #[test]
+    fn color_types_from_ihdr() {
+        let expected = [
+            (0, ColorType::Grayscale, "grayscale"),
+            (2, ColorType::Rgb, "RGB"),
+            (3, ColorType::Palette, "indexed"),
+            (4, ColorType::GrayscaleAlpha, "grayscale+alpha"),
+            (6, ColorType::Rgba, "RGBA"),
+        ];
+        for (value, color_type, name) in expected {
+            let image = PNGFile::read_info(&png(&[ihdr(1, 1, 8, value, 0)])[..]).unwrap();
+            assert_eq!(image.color_type(), color_type);
+            assert_eq!(image.color_type_name(), name);
+        }
+        assert!(matches!(
+            PNGFile::read_info(&png(&[ihdr(1, 1, 8, 5, 0)])[..]),
+            Err(PNGParseError::ParseError("Invalid color type."))
+        ));
+    }
+
+    #[test]
+    fn duplicate_actl_is_an_error() {
+        let bytes = png(&[
+            ihdr(1, 1, 8, 0, 0),
+            animation_control(1),
+            frame_control(0, 1, 1),
+            animation_control(0),
+            idat(&[0, 0]),
+            iend(),
+        ]);
+        match PNGFile::from_bytes(&bytes).unwrap_err() {
+            PNGParseError::InChunk { chunk_type, source, .. } => {
+                assert_eq!(chunk_type, "acTL");
+                assert!(matches!(*source, PNGParseError::ParseError("Duplicate acTL chunk.")));
+            }
+            error => panic!("unexpected error {:?}", error),
+        }
+    }
+
+    #[test]
+    fn get_pixel_of_a_truecolor_image() {
+        let image = PNGFile::from_bytes(&rgb_2x2()).unwrap();
+        assert_eq!(image.get_pixel(0, 0), Some([255, 0, 0, 255]));
+        assert_eq!(image.get_pixel(1, 0), Some([0, 255, 0, 255]));
+        assert_eq!(image.get_pixel(0, 1), Some([0, 0, 255, 255]));
+        assert_eq!(image.get_pixel(1, 1), Some([255, 255, 255, 255]));
+        assert_eq!(image.get_pixel(2, 0), None);
+        assert_eq!(image.get_pixel(0, 2), None);
+    }
+
+    #[test]
+    fn get_pixel_expands_grayscale_and_palette_indices() {
+        let gray = PNGFile::from_bytes(&png(&[ihdr(1, 1, 8, 0, 0), idat(&[0, 77]), iend()])).unwrap();
+        assert_eq!(gray.get_pixel(0, 0), Some([77, 77, 77, 255]));
+        let bytes = png(&[ihdr(2, 1, 8, 3, 0), plte(&[[1, 2, 3], [4, 5, 6]]), idat(&[0, 1, 0]), iend()]);
+        let paletted = PNGFile::from_bytes(&bytes).unwrap();
+        assert_eq!(paletted.get_pixel(0, 0), Some([4, 5, 6, 255]));
+        assert_eq!(paletted.get_pixel(1, 0), Some([1, 2, 3, 255]));
+    }
+
+    #[test]
+    fn io_error_mid_chunk_is_not_eof() {
+        let mut bytes = png(&[ihdr(1, 1, 8, 0, 0)]);
+        bytes.extend_from_slice(&[0, 0, 0, 10]);
+        bytes.extend_from_slice(b"IDAT");
+        bytes.push(0x78);
+        let reader = FailingReader {
+            bytes: bytes.clone(),
+            position: 0,
+            kind: std::io::ErrorKind::BrokenPipe,
+        };
+        let (chunk_type, _, error) = chunk_error(PNGFile::from_reader(reader));
+        assert_eq!(chunk_type, "IDAT");
+        assert!(matches!(error, PNGParseError::Io(e) if e.kind() == std::io::ErrorKind::BrokenPipe));
+        let (_, _, error) = chunk_error(PNGFile::from_bytes(&bytes));
+        assert!(matches!(error, PNGParseError::EOF));
+    }
+
+    #[test]
+    fn crc_covers_the_chunk_type_and_data() {
+        // the well known CRC of an IEND chunk, over its type alone
+        let valid = Chunk {
+            size: 0,
+            type_: *b"IEND",
+            data: vec![],
+            crc: 0xAE42_6082,
+        };
+        assert!(valid.crc_is_valid());
+        assert_ne!(crc(&valid.data), valid.crc);
+        let corrupted = Chunk {
+            data: vec![0],
+            ..valid
+        };
+        assert!(!corrupted.crc_is_valid());
+
+        let mut data = idat(&[0, 0]);
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        let (chunk_type, _, error) = chunk_error(PNGFile::from_bytes(&png(&[ihdr(1, 1, 8, 0, 0), data, iend()])));
+        assert_eq!(chunk_type, "IDAT");
+        assert!(matches!(error, PNGParseError::ParseError("Invalid CRC")));
+    }
+
+    #[test]
+    fn opening_a_missing_file_is_an_error() {
+        let path = std::env::temp_dir().join("ruro-no-such-file.png");
+        match PNGFile::open(path) {
+            Err(PNGParseError::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::NotFound),
+            other => panic!("unexpected result {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn sixteen_bit_grayscale_samples() {
+        let bytes = png(&[ihdr(2, 1, 16, 0, 0), idat(&[0, 0x12, 0x34, 0xFF, 0xFF]), iend()]);
+        let image = PNGFile::from_bytes(&bytes).unwrap();
+        assert_eq!(image.get_pixel16(0, 0), Some([0x1234, 0x1234, 0x1234, 0xFFFF]));
+        assert_eq!(image.get_pixel16(1, 0), Some([0xFFFF; 4]));
+        assert_eq!(image.get_pixel(0, 0), Some([0x12, 0x12, 0x12, 0xFF]));
+    }
+
+    #[test]
+    fn sub_byte_samples_are_unpacked() {
+        // ten pixels, the padding bits of the second byte are set
+        let bytes = png(&[ihdr(10, 1, 1, 0, 0), idat(&[0, 0b1010_0000, 0b0111_1111]), iend()]);
+        let image = PNGFile::from_bytes(&bytes).unwrap();
+        let luma: Vec<u8> = image.pixels().map(|(_, _, pixel)| pixel[0]).collect();
+        assert_eq!(luma, [255, 0, 255, 0, 0, 0, 0, 0, 0, 255]);
+
+        let palette = [[10, 0, 0], [20, 0, 0], [30, 0, 0]];
+        let bytes = png(&[ihdr(3, 1, 4, 3, 0), plte(&palette), idat(&[0, 0x12, 0x0F]), iend()]);
+        let image = PNGFile::from_bytes(&bytes).unwrap();
+        let red: Vec<u8> = image.pixels().map(|(_, _, pixel)| pixel[0]).collect();
+        assert_eq!(red, [20, 30, 10]);
+    }
+
+    #[test]
+    fn interlaced_image_matches_its_progressive_twin() {
+        for (width, height) in [(9, 9), (3, 5), (1, 1)] {
+            let pixels: Vec<u8> = (0..width * height).map(|i| (i * 7) as u8).collect();
+            let progressive: Vec<u8> = pixels.chunks(width).flat_map(|row| [&[0][..], row].concat()).collect();
+            let twin = png(&[ihdr(width as u32, height as u32, 8, 0, 0), idat(&progressive), iend()]);
+            let interlaced = png(&[
+                ihdr(width as u32, height as u32, 8, 0, 1),
+                idat(&adam7_scanlines(&pixels, width, height)),
+                iend(),
+            ]);
+            let twin = PNGFile::from_bytes(&twin).unwrap();
+            let interlaced = PNGFile::from_bytes(&interlaced).unwrap();
+            assert!(interlaced.is_interlaced());
+            assert_eq!(interlaced.into_rgba8().unwrap(), twin.into_rgba8().unwrap());
+        }
+        let unknown = png(&[ihdr(1, 1, 8, 0, 2)]);
+        assert!(matches!(
+            PNGFile::read_info(&unknown[..]),
+            Err(PNGParseError::ParseError("Unknown interlace method."))
+        ));
+    }
+
+    #[test]
+    fn write_round_trips_the_pixels() {
+        let inputs = [
+            rgb_2x2(),
+            png(&[ihdr(2, 1, 8, 3, 0), plte(&[[1, 2, 3], [4, 5, 6]]), chunk(b"tRNS", &[0]), idat(&[0, 1, 0]), iend()]),
+            png(&[ihdr(2, 1, 16, 4, 0), idat(&[0, 1, 2, 3, 4, 5, 6, 7, 8]), iend()]),
+            png(&[ihdr(10, 1, 1, 0, 0), idat(&[0, 0b1010_0000, 0b0100_0000]), iend()]),
+        ];
+        for bytes in inputs {
+            let image = PNGFile::from_bytes(&bytes).unwrap();
+            let mut written = vec![];
+            image.write(&mut written).unwrap();
+            let reread = PNGFile::from_bytes(&written).unwrap();
+            assert_eq!(reread.dimensions(), image.dimensions());
+            assert_eq!((reread.color_type(), reread.bit_depth()), (image.color_type(), image.bit_depth()));
+            assert_eq!(reread.scanlines().unwrap(), image.scanlines().unwrap());
+            assert_eq!(reread.into_rgba8().unwrap(), image.into_rgba8().unwrap());
+        }
+    }
+
+    #[test]
+    fn palette_transparency_from_trns() {
+        let bytes = png(&[
+            ihdr(3, 1, 8, 3, 0),
+            plte(&[[255, 0, 0], [0, 255, 0], [0, 0, 255]]),
+            // the third entry has no alpha and stays opaque
+            chunk(b"tRNS", &[0, 128]),
+            idat(&[0, 0, 1, 2]),
+            iend(),
+        ]);
+        let image = PNGFile::from_bytes(&bytes).unwrap();
+        assert!(matches!(image.transparency(), Some(Transparency::Palette(alphas)) if alphas == &[0, 128]));
+        assert_eq!(image.get_pixel(0, 0), Some([255, 0, 0, 0]));
+        assert_eq!(image.get_pixel(1, 0), Some([0, 255, 0, 128]));
+        assert_eq!(image.get_pixel(2, 0), Some([0, 0, 255, 255]));
+    }
+
+    #[test]
+    fn gamma_from_gama() {
+        let bytes = png(&[ihdr(1, 1, 8, 0, 0), chunk(b"gAMA", &45455u32.to_be_bytes()), idat(&[0, 0]), iend()]);
+        assert_eq!(PNGFile::from_bytes(&bytes).unwrap().gamma(), Some(0.45455));
+        let bytes = png(&[ihdr(1, 1, 8, 0, 0), idat(&[0, 0]), iend()]);
+        assert_eq!(PNGFile::from_bytes(&bytes).unwrap().gamma(), None);
+    }
+
+    #[test]
+    fn text_entries_from_text() {
+        let bytes = png(&[ihdr(1, 1, 8, 0, 0), chunk(b"tEXt", b"Author\0Jane"), idat(&[0, 0]), iend()]);
+        let image = PNGFile::from_bytes(&bytes).unwrap();
+        let entry = &image.text_entries()[0];
+        assert_eq!((entry.keyword.as_str(), entry.text.as_str(), entry.compressed), ("Author", "Jane", false));
+
+        let bytes = png(&[ihdr(1, 1, 8, 0, 0), chunk(b"tEXt", b"AuthorJane"), idat(&[0, 0]), iend()]);
+        let (chunk_type, _, error) = chunk_error(PNGFile::from_bytes(&bytes));
+        assert_eq!(chunk_type, "tEXt");
+        assert!(matches!(error, PNGParseError::ParseError("Invalid tEXt chunk, missing separator.")));
+    }
+
+    #[test]
+    fn compressed_text_from_ztxt() {
+        let mut data = b"Comment\0\0".to_vec();
+        data.extend(deflate(b"Made with ruro").unwrap());
+        let bytes = png(&[ihdr(1, 1, 8, 0, 0), chunk(b"zTXt", &data), idat(&[0, 0]), iend()]);
+        let image = PNGFile::from_bytes(&bytes).unwrap();
+        let entry = &image.text_entries()[0];
+        assert_eq!((entry.keyword.as_str(), entry.text.as_str(), entry.compressed), ("Comment", "Made with ruro", true));
+    }
+
+    #[test]
+    fn physical_dimensions_from_phys() {
+        let mut data = [2835u32.to_be_bytes(), 5670u32.to_be_bytes()].concat();
+        data.push(1);
+        let bytes = png(&[ihdr(1, 1, 8, 0, 0), chunk(b"pHYs", &data), idat(&[0, 0]), iend()]);
+        let image = PNGFile::from_bytes(&bytes).unwrap();
+        assert_eq!(image.physical(), Some((2835, 5670, 1)));
+        let (x, y) = image.dpi().unwrap();
+        assert!((x - 72.009).abs() < 1e-9 && (y - 144.018).abs() < 1e-9);
+
+        data[8] = 0;
+        let bytes = png(&[ihdr(1, 1, 8, 0, 0), chunk(b"pHYs", &data), idat(&[0, 0]), iend()]);
+        assert_eq!(PNGFile::from_bytes(&bytes).unwrap().dpi(), None);
+    }
+
+    #[test]
+    fn invalid_ihdr_fields_are_rejected() {
+        let header = |width: u32, height: u32, fields: [u8; 5]| {
+            let mut data = [width.to_be_bytes(), height.to_be_bytes()].concat();
+            data.extend_from_slice(&fields);
+            png(&[chunk(b"IHDR", &data)])
+        };
+        let cases = [
+            (header(0, 1, [8, 0, 0, 0, 0]), "Invalid image dimensions."),
+            (header(1, 0, [8, 0, 0, 0, 0]), "Invalid image dimensions."),
+            (header(1, 1, [4, 2, 0, 0, 0]), "Invalid bit depth for color type."),
+            (header(1, 1, [16, 3, 0, 0, 0]), "Invalid bit depth for color type."),
+            (header(1, 1, [3, 0, 0, 0, 0]), "Invalid bit depth for color type."),
+            (header(1, 1, [8, 1, 0, 0, 0]), "Invalid color type."),
+            (header(1, 1, [8, 0, 0, 1, 0]), "Unknown filter method."),
+            (header(1, 1, [8, 0, 0, 0, 2]), "Unknown interlace method."),
+        ];
+        for (bytes, message) in cases {
+            match PNGFile::read_info(&bytes[..]) {
+                Err(PNGParseError::ParseError(error)) => assert_eq!(error, message),
+                other => panic!("unexpected result {:?}", other.map(|_| ())),
+            }
+        }
+    }
+
+    #[test]
+    fn chunks_out_of_order_are_rejected() {
+        let palette = || plte(&[[0, 0, 0]]);
+        let cases = [
+            (vec![idat(&[0, 0]), ihdr(1, 1, 8, 0, 0), iend()], "IDAT", "IHDR must be the first chunk."),
+            (vec![ihdr(1, 1, 8, 3, 0), idat(&[0, 0]), palette(), iend()], "PLTE", "Chunk must come before IDAT."),
+            (
+                vec![ihdr(1, 1, 8, 0, 0), idat(&[0]), chunk(b"tEXt", b"a\0b"), idat(&[0]), iend()],
+                "IDAT",
+                "IDAT chunks must be contiguous.",
+            ),
+            (vec![ihdr(1, 1, 8, 0, 0), ihdr(1, 1, 8, 0, 0), iend()], "IHDR", "Duplicate IHDR chunk."),
+        ];
+        for (chunks, expected_type, message) in cases {
+            let (chunk_type, _, error) = chunk_error(PNGFile::from_bytes(&png(&chunks)));
+            assert_eq!(chunk_type, expected_type);
+            assert!(matches!(error, PNGParseError::ParseError(error) if error == message));
+        }
+    }
+
+    #[test]
+    fn palette_entries_from_plte() {
+        let entries = [[1, 2, 3], [4, 5, 6], [7, 8, 9], [10, 11, 12]];
+        let bytes = png(&[ihdr(1, 1, 8, 3, 0), plte(&entries), idat(&[0, 3]), iend()]);
+        let image = PNGFile::from_bytes(&bytes).unwrap();
+        assert_eq!(image.palette().len(), 4);
+        assert_eq!(image.palette()[3], Rgb { r: 10, g: 11, b: 12 });
+
+        let bytes = png(&[ihdr(1, 1, 8, 3, 0), chunk(b"PLTE", &[1, 2, 3, 4]), idat(&[0, 0]), iend()]);
+        let (_, _, error) = chunk_error(PNGFile::from_bytes(&bytes));
+        assert!(matches!(error, PNGParseError::ParseError("Invalid PLTE chunk length.")));
+    }
+
+    #[test]
+    fn ignore_crc_decodes_a_chunk_with_a_bad_crc() {
+        let bytes = png(&[ihdr(1, 1, 8, 0, 0), corrupt(idat(&[0, 42])), iend()]);
+        let (chunk_type, _, error) = chunk_error(Decoder::new().decode(&bytes[..]));
+        assert_eq!(chunk_type, "IDAT");
+        assert!(matches!(error, PNGParseError::ParseError("Invalid CRC")));
+        let image = Decoder::new().ignore_crc(true).decode(&bytes[..]).unwrap();
+        assert_eq!(image.get_pixel(0, 0), Some([42, 42, 42, 255]));
+    }
+
+    #[test]
+    fn image_data_beyond_the_size_limit_is_rejected() {
+        // a megabyte of zeroes compresses to about a kilobyte
+        let bomb = png(&[ihdr(1, 1, 8, 0, 0), idat(&vec![0; 1 << 20]), iend()]);
+        assert!(bomb.len() < 4096);
+        let (_, _, error) = chunk_error(PNGFile::from_bytes(&bomb));
+        assert!(matches!(error, PNGParseError::ParseError("Image data exceeds the decompressed size limit.")));
+
+        let bytes = png(&[ihdr(4, 4, 8, 0, 0), idat(&[0; 20]), iend()]);
+        let (_, _, error) = chunk_error(Decoder::new().max_decompressed_size(10).decode(&bytes[..]));
+        assert!(matches!(error, PNGParseError::ParseError("Image data exceeds the decompressed size limit.")));
+        assert!(Decoder::new().max_decompressed_size(20).decode(&bytes[..]).is_ok());
+    }
+
+    #[test]
+    fn image_data_size_must_match_the_dimensions() {
+        for len in [4, 8] {
+            let bytes = png(&[ihdr(2, 2, 8, 0, 0), idat(&vec![0; len]), iend()]);
+            let decoder = Decoder::new().max_decompressed_size(100);
+            let (_, _, error) = chunk_error(decoder.decode(&bytes[..]));
+            assert!(matches!(error, PNGParseError::ParseError("Image data size doesn't match the image dimensions.")));
+        }
+    }
+
+    #[test]
+    fn background_for_each_color_type() {
+        let background = |chunks: Vec<Vec<u8>>| PNGFile::from_bytes(&png(&chunks)).unwrap().background();
+        let palette = background(vec![
+            ihdr(1, 1, 8, 3, 0),
+            plte(&[[0, 0, 0], [255, 128, 0]]),
+            chunk(b"bKGD", &[1]),
+            idat(&[0, 0]),
+            iend(),
+        ]);
+        assert_eq!(palette, Some(Rgb16 { r: 65535, g: 128 * 257, b: 0 }));
+        for color_type in [0, 4] {
+            let pixel = vec![0; 1 + ColorType::try_from(color_type).unwrap().channels() as usize];
+            let gray = background(vec![ihdr(1, 1, 8, color_type, 0), chunk(b"bKGD", &[0, 51]), idat(&pixel), iend()]);
+            assert_eq!(gray, Some(Rgb16 { r: 51 * 257, g: 51 * 257, b: 51 * 257 }));
+        }
+        let sixteen_bit = background(vec![ihdr(1, 1, 16, 0, 0), chunk(b"bKGD", &[0x12, 0x34]), idat(&[0, 0, 0]), iend()]);
+        assert_eq!(sixteen_bit, Some(Rgb16 { r: 0x1234, g: 0x1234, b: 0x1234 }));
+        for color_type in [2, 6] {
+            let pixel = vec![0; 1 + ColorType::try_from(color_type).unwrap().channels() as usize];
+            let rgb = background(vec![
+                ihdr(1, 1, 8, color_type, 0),
+                chunk(b"bKGD", &[0, 1, 0, 2, 0, 3]),
+                idat(&pixel),
+                iend(),
+            ]);
+            assert_eq!(rgb, Some(Rgb16 { r: 257, g: 2 * 257, b: 3 * 257 }));
+        }
+    }
+
+    #[test]
+    fn from_bytes_decodes_like_open() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/benches/fixtures/paletted.png");
+        let bytes: Vec<u8> = std::fs::read(path).unwrap();
+        let from_bytes = PNGFile::from_bytes(&bytes).unwrap();
+        let opened = PNGFile::open(path).unwrap();
+        assert_eq!(from_bytes.dimensions(), (64, 64));
+        assert_eq!(from_bytes.into_rgba8().unwrap(), opened.into_rgba8().unwrap());
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn init_still_opens_files() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/benches/fixtures/paletted.png");
+        let mut image = PNGFile::init(path.to_string()).unwrap();
+        image.parse().unwrap();
+        assert_eq!(image.into_rgba8().unwrap(), PNGFile::open(path).unwrap().into_rgba8().unwrap());
+        let missing = std::env::temp_dir().join("ruro-no-such-file.png");
+        assert!(matches!(
+            PNGFile::init(missing.to_string_lossy().into_owned()),
+            Err(PNGParseError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn into_rgba8_is_row_major_rgba() {
+        let rgba = PNGFile::from_bytes(&rgb_2x2()).unwrap().into_rgba8().unwrap();
+        assert_eq!(rgba.len(), 2 * 2 * 4);
+        assert_eq!(rgba[..4], [255, 0, 0, 255]);
+        assert_eq!(rgba[8..12], [0, 0, 255, 255]);
+        let header = PNGFile::read_info(&rgb_2x2()[..]).unwrap();
+        assert!(matches!(header.into_rgba8(), Err(PNGParseError::ParseError("No decoded image data."))));
+    }
+
+    #[test]
+    fn incremental_crc_matches_crc() {
+        let data = b"IHDR and some chunk data";
+        for split in [0, 1, 4, data.len()] {
+            let mut crc32 = Crc32::new();
+            crc32.update(&data[..split]);
+            crc32.update(&data[split..]);
+            assert_eq!(crc32.finalize(), crc(data));
+        }
+        assert_eq!(crc(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn crc_table_matches_the_zlib_polynomial() {
+        assert_eq!(CRC_TABLE[..4], [0x0000_0000, 0x7707_3096, 0xEE0E_612C, 0x9909_51BA]);
+        assert_eq!(CRC_TABLE[255], 0x2D02_EF8D);
+    }
+
+    #[test]
+    fn scanlines_hold_the_raw_samples() {
+        let rows = PNGFile::from_bytes(&rgb_2x2()).unwrap();
+        let rows = rows.scanlines().unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1], [0, 0, 255, 255, 255, 255]);
+        let bytes = png(&[ihdr(3, 2, 16, 4, 0), idat(&[0; 2 * 13]), iend()]);
+        let image = PNGFile::from_bytes(&bytes).unwrap();
+        assert!(image.scanlines().unwrap().iter().all(|row| row.len() == 3 * 4));
+    }
+
+    #[test]
+    fn international_text_from_itxt() {
+        let mut compressed = b"Title\0\x01\0fr\0Titre\0".to_vec();
+        compressed.extend(deflate("Été".as_bytes()).unwrap());
+        let bytes = png(&[
+            ihdr(1, 1, 8, 0, 0),
+            chunk(b"iTXt", "Author\0\0\0\0\0Zoë".as_bytes()),
+            chunk(b"iTXt", &compressed),
+            idat(&[0, 0]),
+            iend(),
+        ]);
+        let image = PNGFile::from_bytes(&bytes).unwrap();
+        let [plain, zipped] = image.international_text_entries() else {
+            panic!("expected two iTXt entries");
+        };
+        assert_eq!((plain.keyword.as_str(), plain.text.as_str(), plain.compressed), ("Author", "Zoë", false));
+        assert_eq!((plain.language.as_str(), plain.translated_keyword.as_str()), ("", ""));
+        assert_eq!((zipped.keyword.as_str(), zipped.text.as_str(), zipped.compressed), ("Title", "Été", true));
+        assert_eq!((zipped.language.as_str(), zipped.translated_keyword.as_str()), ("fr", "Titre"));
+    }
+
+    #[test]
+    fn corrupt_ancillary_chunk_is_recorded() {
+        let bytes = png(&[
+            ihdr(1, 1, 8, 0, 0),
+            corrupt(chunk(b"gAMA", &45455u32.to_be_bytes())),
+            idat(&[0, 9]),
+            iend(),
+        ]);
+        let image = PNGFile::from_bytes(&bytes).unwrap();
+        assert_eq!(image.get_pixel(0, 0), Some([9, 9, 9, 255]));
+        assert_eq!(image.gamma(), None);
+        match image.crc_errors() {
+            [PNGParseError::InChunk { chunk_type, offset, source }] => {
+                assert_eq!((chunk_type.as_str(), *offset), ("gAMA", 33));
+                assert!(matches!(**source, PNGParseError::ParseError("Invalid CRC")));
+            }
+            errors => panic!("unexpected errors {:?}", errors),
+        }
+    }
+
+    #[test]
+    fn two_frame_animation() {
+        let bytes = png(&[
+            ihdr(2, 1, 8, 0, 0),
+            animation_control(2),
+            frame_control(0, 2, 1),
+            idat(&[0, 10, 20]),
+            frame_control(1, 1, 1),
+            frame_data(2, &[0, 30]),
+            iend(),
+        ]);
+        let image = PNGFile::from_bytes(&bytes).unwrap();
+        let animation = image.animation().unwrap();
+        assert_eq!((animation.num_frames(), animation.num_plays()), (2, 0));
+        let [first, second] = animation.frames() else {
+            panic!("expected two frames");
+        };
+        assert_eq!((first.width, first.height, first.delay()), (2, 1, 1.0));
+        assert_eq!((second.width, second.height, second.x_offset, second.y_offset), (1, 1, 0, 0));
+        assert_eq!(first.pixels, [10, 10, 10, 255, 20, 20, 20, 255]);
+        assert_eq!(second.pixels, [30, 30, 30, 255]);
+    }
+
+    #[test]
+    fn over_blend_composites_onto_the_previous_frame() {
+        let frame = |blend_op, pixels: Vec<u8>| Frame {
+            width: 1,
+            height: 1,
+            x_offset: 0,
+            y_offset: 0,
+            delay_num: 1,
+            delay_den: 1,
+            dispose_op: DisposeOp::None,
+            blend_op,
+            pixels,
+        };
+        let animation = Apng {
+            width: 1,
+            height: 1,
+            num_frames: 2,
+            num_plays: 0,
+            frames: vec![
+                frame(BlendOp::Source, vec![0, 0, 255, 255]),
+                frame(BlendOp::Over, vec![255, 0, 0, 51]),
+            ],
+            default_image_is_first_frame: false,
+        };
+        let rendered = animation.render_frame(1).unwrap();
+        assert_eq!(rendered.get_pixel(0, 0), Some([51, 0, 204, 255]));
+        assert_eq!(animation.render_frame(0).unwrap().get_pixel(0, 0), Some([0, 0, 255, 255]));
+    }
+
+    #[test]
+    fn srgb_rendering_intents() {
+        let intents = [
+            RenderingIntent::Perceptual,
+            RenderingIntent::RelativeColorimetric,
+            RenderingIntent::Saturation,
+            RenderingIntent::AbsoluteColorimetric,
+        ];
+        for (value, intent) in intents.into_iter().enumerate() {
+            let bytes = png(&[ihdr(1, 1, 8, 0, 0), chunk(b"sRGB", &[value as u8]), idat(&[0, 0]), iend()]);
+            assert_eq!(PNGFile::from_bytes(&bytes).unwrap().srgb_intent(), Some(intent));
+        }
+        let bytes = png(&[ihdr(1, 1, 8, 0, 0), chunk(b"sRGB", &[4]), idat(&[0, 0]), iend()]);
+        let (_, _, error) = chunk_error(PNGFile::from_bytes(&bytes));
+        assert!(matches!(error, PNGParseError::ParseError("Invalid sRGB chunk.")));
+    }
+
+    #[test]
+    fn chromaticities_from_chrm() {
+        // the sRGB primaries and D65 white point
+        let values: [u32; 8] = [31270, 32900, 64000, 33000, 30000, 60000, 15000, 6000];
+        let data: Vec<u8> = values.iter().flat_map(|v| v.to_be_bytes()).collect();
+        let bytes = png(&[ihdr(1, 1, 8, 0, 0), chunk(b"cHRM", &data), idat(&[0, 0]), iend()]);
+        let chromaticities = PNGFile::from_bytes(&bytes).unwrap().chromaticities().unwrap();
+        assert_eq!(
+            chromaticities,
+            Chromaticities {
+                white_x: 31270,
+                white_y: 32900,
+                red_x: 64000,
+                red_y: 33000,
+                green_x: 30000,
+                green_y: 60000,
+                blue_x: 15000,
+                blue_y: 6000,
+            }
+        );
+        assert_eq!(chromaticities.white_point(), (0.3127, 0.329));
+        assert_eq!(chromaticities.blue(), (0.15, 0.06));
+    }
+
+    #[test]
+    fn icc_profile_from_iccp() {
+        let profile = vec![7u8; 3000];
+        let mut data = b"Display P3\0\0".to_vec();
+        data.extend(deflate(&profile).unwrap());
+        let bytes = png(&[ihdr(1, 1, 8, 0, 0), chunk(b"iCCP", &data), idat(&[0, 0]), iend()]);
+        let image = PNGFile::from_bytes(&bytes).unwrap();
+        assert_eq!(image.icc_profile_name(), Some("Display P3"));
+        assert_eq!(image.icc_profile().map(|profile| profile.len()), Some(3000));
+    }
+
+    #[test]
+    fn iter_chunks_lists_every_chunk() {
+        let bytes = png(&[
+            ihdr(1, 1, 8, 0, 0),
+            chunk(b"tEXt", b"a\0b"),
+            corrupt(chunk(b"prIv", b"data")),
+            idat(&[0, 0]),
+            iend(),
+        ]);
+        let headers: Vec<ChunkHeader> = PNGFile::iter_chunks(&bytes[..]).map(Result::unwrap).collect();
+        let types: Vec<[u8; 4]> = headers.iter().map(|header| header.type_).collect();
+        assert_eq!(types, [*b"IHDR", *b"tEXt", *b"prIv", *b"IDAT", *b"IEND"]);
+        assert_eq!(headers[2].size, 4);
+        assert_eq!(headers.iter().filter(|header| !header.crc_ok).count(), 1);
+    }
+
+    #[test]
+    fn non_ascii_chunk_types_are_kept_as_bytes() {
+        let type_ = [b'p', 0xE9, b'A', b'x'];
+        let bytes = png(&[ihdr(1, 1, 8, 0, 0), chunk(&type_, b"data"), idat(&[0, 0]), iend()]);
+        let image = PNGFile::from_bytes(&bytes).unwrap();
+        let kept = image.chunk_by_type(&type_).unwrap();
+        assert_eq!(kept.chunk_type(), &type_);
+        assert_eq!(kept.type_str(), "p\u{FFFD}Ax");
+        assert_eq!(kept.data(), b"data");
+    }
+
+    #[test]
+    fn chunk_property_bits() {
+        let properties = |type_: &[u8; 4]| {
+            let chunk = Chunk {
+                size: 0,
+                type_: *type_,
+                data: vec![],
+                crc: 0,
+            };
+            [chunk.is_ancillary(), chunk.is_private(), chunk.is_reserved_bit_set(), chunk.is_safe_to_copy()]
+        };
+        assert_eq!(properties(b"IHDR"), [false, false, false, false]);
+        assert_eq!(properties(b"tEXt"), [true, false, false, true]);
+        assert_eq!(properties(b"gAMA"), [true, false, false, false]);
+        assert_eq!(properties(b"prIv"), [true, true, false, true]);
+        assert_eq!(properties(b"XXxX"), [false, false, true, false]);
+    }
+
+    #[test]
+    fn display_summarizes_the_image() {
+        let image = PNGFile::from_bytes(&rgb_2x2()).unwrap();
+        assert_eq!(image.dimensions(), (2, 2));
+        assert_eq!(image.to_string(), "PNG 2x2 8-bit RGB, 0 ancillary chunks");
+        let bytes = png(&[ihdr(1, 1, 8, 6, 1), chunk(b"tEXt", b"a\0b"), idat(&[0, 0, 0, 0, 0]), iend()]);
+        let image = PNGFile::from_bytes(&bytes).unwrap();
+        assert_eq!(image.to_string(), "PNG 1x1 8-bit RGBA interlaced, 1 ancillary chunk");
+    }
+
+    #[test]
+    fn many_small_idat_chunks() {
+        let scanlines: Vec<u8> = (0..16u8).flat_map(|y| [&[0][..], &[y; 16][..]].concat()).collect();
+        let compressed = deflate(&scanlines).unwrap();
+        let mut chunks = vec![ihdr(16, 16, 8, 0, 0)];
+        chunks.extend(compressed.chunks(3).map(|piece| chunk(b"IDAT", piece)));
+        chunks.push(chunk(b"IDAT", &[]));
+        chunks.push(iend());
+        let split = PNGFile::from_bytes(&png(&chunks)).unwrap();
+        let whole = PNGFile::from_bytes(&png(&[ihdr(16, 16, 8, 0, 0), idat(&scanlines), iend()])).unwrap();
+        assert_eq!(split.into_rgba8().unwrap(), whole.into_rgba8().unwrap());
+    }
+
+    #[test]
+    fn paeth_predictor_reference_cases() {
+        // (a, b, c, expected): p = a + b - c picks the closest neighbor
+        let cases = [
+            (10, 20, 10, 20),
+            (20, 10, 10, 20),
+            (10, 10, 20, 10),
+            (100, 50, 60, 100),
+            (50, 100, 60, 100),
+            (30, 40, 35, 35),
+            (200, 10, 100, 100),
+            (0, 255, 255, 0),
+            (255, 0, 255, 0),
+            (7, 7, 7, 7),
+        ];
+        for (a, b, c, expected) in cases {
+            assert_eq!(paeth_predictor(a, b, c), expected, "a={} b={} c={}", a, b, c);
+        }
+        // ties go to a, then b, and only then to c
+        assert_eq!(paeth_predictor(10, 25, 20), 10);
+        assert_eq!(paeth_predictor(25, 10, 20), 10);
+        assert_eq!(paeth_predictor(5, 5, 0), 5);
+    }
+
+    #[test]
+    fn filter_strategies_round_trip() {
+        let pixels: Vec<u8> = (0..8 * 6).flat_map(|i: u32| [(i * 5) as u8, (i * i) as u8, 255 - i as u8, (i * 40) as u8]).collect();
+        let image = PNGFile::from_rgba8(8, 6, pixels.clone()).unwrap();
+        let strategies = [
+            FilterStrategy::MinSumOfAbsolute,
+            FilterStrategy::None,
+            FilterStrategy::Fixed(1),
+            FilterStrategy::Fixed(2),
+            FilterStrategy::Fixed(3),
+            FilterStrategy::Fixed(4),
+        ];
+        for strategy in strategies {
+            let mut written = vec![];
+            Encoder::new().filter(strategy).encode(&image, &mut written).unwrap();
+            assert_eq!(PNGFile::from_bytes(&written).unwrap().into_rgba8().unwrap(), pixels);
+        }
+        assert!(matches!(
+            Encoder::new().filter(FilterStrategy::Fixed(5)).encode(&image, &mut vec![]),
+            Err(PNGParseError::UnknownFilter(5))
+        ));
+    }
+
+    #[test]
+    fn read_info_only_reads_the_header() {
+        let bytes = rgb_2x2();
+        let mut reader = std::io::Cursor::new(&bytes);
+        let header = PNGFile::read_info(&mut reader).unwrap();
+        assert_eq!(reader.position(), 33);
+        assert_eq!((header.dimensions(), header.color_type()), ((2, 2), ColorType::Rgb));
+        assert!(header.scanlines().is_err());
+    }
+
+    #[test]
+    fn unknown_compression_method_is_rejected() {
+        let mut bytes = rgb_2x2();
+        // the compression method byte of IHDR, then fix up the CRC
+        bytes[8 + 8 + 10] = 1;
+        let crc_offset = 8 + 8 + 13;
+        let fixed = crc(&bytes[12..crc_offset]).to_be_bytes();
+        bytes[crc_offset..crc_offset + 4].copy_from_slice(&fixed);
+        let (chunk_type, _, error) = chunk_error(PNGFile::from_bytes(&bytes));
+        assert_eq!(chunk_type, "IHDR");
+        assert!(matches!(error, PNGParseError::ParseError("Unknown compression method.")));
+    }
+
+    #[test]
+    fn channels_and_bytes_per_pixel() {
+        let cases = [
+            (1, 3, 1, 1),
+            (8, 3, 1, 1),
+            (8, 0, 1, 1),
+            (16, 0, 1, 2),
+            (8, 4, 2, 2),
+            (8, 2, 3, 3),
+            (16, 2, 3, 6),
+            (8, 6, 4, 4),
+            (16, 6, 4, 8),
+        ];
+        for (bit_depth, color_type, channels, bytes_per_pixel) in cases {
+            let image = PNGFile::read_info(&png(&[ihdr(1, 1, bit_depth, color_type, 0)])[..]).unwrap();
+            assert_eq!((image.channels(), image.bytes_per_pixel()), (channels, bytes_per_pixel));
+        }
+    }
+
+    #[test]
+    fn ancillary_chunks_in_file_order() {
+        let bytes = png(&[
+            ihdr(1, 1, 8, 0, 0),
+            chunk(b"zzZz", b"1"),
+            chunk(b"tEXt", b"a\0b"),
+            chunk(b"aaAa", b"2"),
+            idat(&[0, 0]),
+            chunk(b"mmMm", b"3"),
+            iend(),
+        ]);
+        let image = PNGFile::from_bytes(&bytes).unwrap();
+        let data: Vec<&[u8]> = image.ancillary_chunks().map(|chunk| chunk.data()).collect();
+        assert_eq!(data, [b"1", b"2", b"3"]);
+    }
+
+    #[test]
+    fn safe_to_copy_chunks_survive_a_rewrite() {
+        let bytes = png(&[
+            ihdr(1, 1, 8, 0, 0),
+            chunk(b"pHYs", &[0, 0, 11, 19, 0, 0, 11, 19, 1]),
+            chunk(b"sPLT", &[&b"grays\0\x10"[..], &[0, 1, 0, 2, 0, 3, 0xFF, 0xFF, 0, 9]].concat()),
+            chunk(b"tEXt", b"Title\0ruro"),
+            chunk(b"prIv", b"safe"),
+            chunk(b"prIV", b"unsafe"),
+            idat(&[0, 0]),
+            iend(),
+        ]);
+        let image = PNGFile::from_bytes(&bytes).unwrap();
+        let mut copied = vec![];
+        Encoder::new().copy_safe_chunks(true).encode(&image, &mut copied).unwrap();
+        let types: Vec<String> = PNGFile::iter_chunks(&copied[..]).map(|header| header.unwrap().type_str().into_owned()).collect();
+        assert_eq!(types, ["IHDR", "pHYs", "sPLT", "tEXt", "prIv", "IDAT", "IEND"]);
+        let copied = PNGFile::from_bytes(&copied).unwrap();
+        assert_eq!(copied.physical(), Some((2835, 2835, 1)));
+        let [palette] = copied.suggested_palettes() else {
+            panic!("expected one sPLT palette");
+        };
+        assert_eq!((palette.name.as_str(), palette.sample_depth), ("grays", 16));
+        assert_eq!((palette.entries[0].blue, palette.entries[0].alpha, palette.entries[0].frequency), (3, 0xFFFF, 9));
+        assert_eq!(copied.text_entries()[0].text, "ruro");
+        let chunks: Vec<(&[u8; 4], &[u8])> = copied.chunks().iter().map(|chunk| (chunk.chunk_type(), chunk.data())).collect();
+        assert_eq!(chunks, [(b"prIv", &b"safe"[..])]);
+
+        let mut written = vec![];
+        image.write(&mut written).unwrap();
+        assert!(PNGFile::from_bytes(&written).unwrap().chunks().is_empty());
+    }
+
+    #[test]
+    fn significant_bits_from_sbit() {
+        let bytes = png(&[ihdr(1, 1, 8, 2, 0), chunk(b"sBIT", &[5, 6, 5]), idat(&[0, 0, 0, 0]), iend()]);
+        assert_eq!(PNGFile::from_bytes(&bytes).unwrap().significant_bits(), Some(&[5, 6, 5][..]));
+        let bytes = png(&[ihdr(1, 1, 8, 2, 0), chunk(b"sBIT", &[5, 9, 5]), idat(&[0, 0, 0, 0]), iend()]);
+        let (_, _, error) = chunk_error(PNGFile::from_bytes(&bytes));
+        assert!(matches!(error, PNGParseError::ParseError("Invalid sBIT chunk.")));
+    }
+
+    #[test]
+    fn modification_time_from_time() {
+        let bytes = png(&[ihdr(1, 1, 8, 0, 0), chunk(b"tIME", &[0x07, 0xE8, 2, 29, 23, 59, 60]), idat(&[0, 0]), iend()]);
+        let time = PNGFile::from_bytes(&bytes).unwrap().modified().unwrap();
+        assert_eq!(
+            time,
+            TimeStamp {
+                year: 2024,
+                month: 2,
+                day: 29,
+                hour: 23,
+                minute: 59,
+                second: 60,
+            }
+        );
+        let bytes = png(&[ihdr(1, 1, 8, 0, 0), chunk(b"tIME", &[0x07, 0xE8, 13, 1, 0, 0, 0]), idat(&[0, 0]), iend()]);
+        let (_, _, error) = chunk_error(PNGFile::from_bytes(&bytes));
+        assert!(matches!(error, PNGParseError::ParseError("Invalid tIME chunk.")));
+    }
+
+    #[test]
+    fn corrupt_chunk_reports_its_offset() {
+        // signature, IHDR and the 22 byte tEXt chunk come first
+        let bytes = png(&[
+            ihdr(1, 1, 8, 0, 0),
+            chunk(b"tEXt", b"Title\0ruro"),
+            chunk(b"gAMA", &[0, 1]),
+            idat(&[0, 0]),
+            iend(),
+        ]);
+        let error = PNGFile::from_bytes(&bytes).unwrap_err();
+        // reporters walking the source chain print each message once
+        assert_eq!(error.to_string(), "in `gAMA` chunk at byte 55");
+        let source = std::error::Error::source(&error).map(|source| source.to_string());
+        assert_eq!(source.as_deref(), Some("Parse error: `Invalid gAMA chunk.`."));
+        let (chunk_type, offset, _) = chunk_error(Err(error));
+        assert_eq!((chunk_type.as_str(), offset), ("gAMA", 55));
+    }
+
+    #[test]
+    fn cloned_image_keeps_its_properties() {
+        let image = PNGFile::from_bytes(&rgb_2x2()).unwrap();
+        let clone = image.clone();
+        drop(image);
+        assert_eq!(clone.dimensions(), (2, 2));
+        assert_eq!(clone.get_pixel(1, 1), Some([255, 255, 255, 255]));
+        assert!(format!("{:?}", clone).starts_with("PNGFile { width: 2, height: 2, bit_depth: 8, color_type: Rgb"));
+    }
+
+    #[test]
+    fn missing_iend_and_truncated_idat() {
+        let stripped = png(&[ihdr(1, 1, 8, 0, 0), idat(&[0, 0])]);
+        assert!(matches!(PNGFile::from_bytes(&stripped), Err(PNGParseError::ParseError("Missing IEND chunk."))));
+        let mut truncated = stripped;
+        truncated.truncate(truncated.len() - 6);
+        let (chunk_type, offset, error) = chunk_error(PNGFile::from_bytes(&truncated));
+        assert_eq!((chunk_type.as_str(), offset), ("IDAT", 33));
+        assert!(matches!(error, PNGParseError::EOF));
+    }
+
+    #[test]
+    fn suggested_palette_from_splt() {
+        let mut data = b"web safe\0\x08".to_vec();
+        data.extend_from_slice(&[255, 0, 0, 255, 0, 10]);
+        data.extend_from_slice(&[0, 0, 255, 128, 0, 5]);
+        let bytes = png(&[ihdr(1, 1, 8, 2, 0), chunk(b"sPLT", &data), idat(&[0, 0, 0, 0]), iend()]);
+        let image = PNGFile::from_bytes(&bytes).unwrap();
+        let [palette] = image.suggested_palettes() else {
+            panic!("expected one sPLT palette");
+        };
+        assert_eq!((palette.name.as_str(), palette.sample_depth, palette.entries.len()), ("web safe", 8, 2));
+        assert_eq!(
+            palette.entries[1],
+            SuggestedPaletteEntry {
+                red: 0,
+                green: 0,
+                blue: 255,
+                alpha: 128,
+                frequency: 5,
+            }
+        );
+    }
+
+    #[test]
+    fn premultiplied_alpha() {
+        let image = PNGFile::from_rgba8(3, 1, vec![255, 0, 0, 128, 10, 20, 30, 255, 200, 200, 200, 0]).unwrap();
+        assert_eq!(
+            image.into_rgba8_premultiplied().unwrap(),
+            [128, 0, 0, 128, 10, 20, 30, 255, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn is_png_checks_the_signature() {
+        assert!(is_png(&rgb_2x2()));
+        assert!(is_png(&PNG_SIGNATURE));
+        assert!(!is_png(&[0xFF, 0xD8, 0xFF, 0xE0, 0, 0x10, b'J', b'F', b'I', b'F']));
+        assert!(!is_png(&PNG_SIGNATURE[..7]));
+        assert!(!is_png(&[]));
+    }
+
+    #[test]
+    fn has_alpha_by_color_type_and_trns() {
+        let header = |color_type| PNGFile::read_info(&png(&[ihdr(1, 1, 8, color_type, 0)])[..]).unwrap();
+        let alpha: Vec<bool> = [0, 2, 3, 4, 6].into_iter().map(|color_type| header(color_type).has_alpha()).collect();
+        assert_eq!(alpha, [false, false, false, true, true]);
+        let bytes = png(&[ihdr(1, 1, 8, 3, 0), plte(&[[0, 0, 0]]), chunk(b"tRNS", &[0]), idat(&[0, 0]), iend()]);
+        assert!(PNGFile::from_bytes(&bytes).unwrap().has_alpha());
+        let bytes = png(&[ihdr(1, 1, 8, 0, 0), chunk(b"tRNS", &[0, 0]), idat(&[0, 0]), iend()]);
+        assert!(PNGFile::from_bytes(&bytes).unwrap().has_alpha());
+    }
+
+    #[test]
+    fn decode_rows_matches_the_full_decode() {
+        // Sub filtered rows, each depends on the one decoded before it
+        let scanlines: Vec<u8> = (0..5u8).flat_map(|y| [1, y * 10, 1, 2]).collect();
+        let image = PNGFile::from_bytes(&png(&[ihdr(3, 5, 8, 0, 0), idat(&scanlines), iend()])).unwrap();
+        let full = image.into_rgba8().unwrap();
+        let row_len = 3 * 4;
+        assert_eq!(image.decode_rows(1, 4).unwrap(), full[row_len..4 * row_len]);
+        assert_eq!(image.decode_rows(0, 5).unwrap(), full);
+        assert!(image.decode_rows(2, 2).unwrap().is_empty());
+        assert!(matches!(image.decode_rows(3, 6), Err(PNGParseError::ParseError("Row range is outside the image."))));
+    }
+
+    #[test]
+    fn save_and_open_round_trip() {
+        let image = PNGFile::from_bytes(&rgb_2x2()).unwrap();
+        let path = std::env::temp_dir().join(format!("ruro-save-{}.png", std::process::id()));
+        image.save(&path).unwrap();
+        let reopened = PNGFile::open(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(reopened.unwrap().into_rgba8().unwrap(), image.into_rgba8().unwrap());
+    }
+
+    #[test]
+    fn default_image_inside_and_outside_the_animation() {
+        let first_frame = png(&[
+            ihdr(1, 1, 8, 0, 0),
+            animation_control(1),
+            frame_control(0, 1, 1),
+            idat(&[0, 10]),
+            iend(),
+        ]);
+        let image = PNGFile::from_bytes(&first_frame).unwrap();
+        let animation = image.animation().unwrap();
+        assert_eq!(animation.num_frames(), 1);
+        assert!(animation.default_image_is_first_frame());
+        assert_eq!(animation.frames()[0].pixels, [10, 10, 10, 255]);
+
+        let separate = png(&[
+            ihdr(1, 1, 8, 0, 0),
+            animation_control(1),
+            idat(&[0, 10]),
+            frame_control(0, 1, 1),
+            frame_data(1, &[0, 20]),
+            iend(),
+        ]);
+        let image = PNGFile::from_bytes(&separate).unwrap();
+        let animation = image.animation().unwrap();
+        assert_eq!(animation.num_frames(), 1);
+        assert!(!animation.default_image_is_first_frame());
+        assert_eq!(animation.frames()[0].pixels, [20, 20, 20, 255]);
+        assert_eq!(image.get_pixel(0, 0), Some([10, 10, 10, 255]));
+    }
+
+    #[test]
+    fn zero_length_chunk_before_iend() {
+        let bytes = png(&[ihdr(1, 1, 8, 0, 0), idat(&[0, 0]), chunk(b"prIv", &[]), iend()]);
+        let image = PNGFile::from_bytes(&bytes).unwrap();
+        let empty = image.chunk_by_type(b"prIv").unwrap();
+        assert_eq!((empty.size(), empty.data()), (0, &[][..]));
+        assert!(empty.crc_is_valid());
+    }
+
+    #[test]
+    fn trailing_bytes_after_iend() {
+        let mut bytes = png(&[ihdr(1, 1, 8, 0, 0), idat(&[0, 0]), iend()]);
+        bytes.extend_from_slice(b"appended");
+        assert!(PNGFile::from_bytes(&bytes).unwrap().trailing_bytes().is_empty());
+        let image = Decoder::new().keep_trailing_bytes(true).decode(&bytes[..]).unwrap();
+        assert_eq!(image.trailing_bytes(), b"appended");
+    }
+
+    #[test]
+    fn grayscale_alpha_expansion() {
+        let bytes = png(&[ihdr(2, 1, 8, 4, 0), idat(&[0, 200, 128, 50, 255]), iend()]);
+        let image = PNGFile::from_bytes(&bytes).unwrap();
+        assert_eq!(image.into_rgba8().unwrap(), [200, 200, 200, 128, 50, 50, 50, 255]);
+
+        let bytes = png(&[ihdr(1, 1, 16, 4, 0), idat(&[0, 0x40, 0x01, 0x80, 0x02]), iend()]);
+        let image = PNGFile::from_bytes(&bytes).unwrap();
+        assert_eq!(image.get_pixel16(0, 0), Some([0x4001, 0x4001, 0x4001, 0x8002]));
+        assert_eq!(image.get_pixel(0, 0), Some([0x40, 0x40, 0x40, 0x80]));
+    }
+
+    #[test]
+    fn malformed_chunks_are_errors() {
+        let with = |extra: Vec<u8>| png(&[ihdr(1, 1, 8, 0, 0), extra, idat(&[0, 0]), iend()]);
+        let inputs = [
+            png(&[chunk(b"IHDR", &[0; 10])]),
+            with(chunk(b"sRGB", &[])),
+            with(chunk(b"pHYs", &[0; 3])),
+            with(chunk(b"tIME", &[0; 2])),
+            with(chunk(b"sPLT", b"name\0")),
+            with(chunk(b"iTXt", b"key\0")),
+            with(chunk(b"tRNS", &[0])),
+            with(chunk(b"bKGD", &[])),
+            png(&[ihdr(1, 1, 8, 0, 0), animation_control(1), frame_control(0, 1, 1), idat(&[0, 0]), chunk(b"fdAT", &[0]), iend()]),
+            PNG_SIGNATURE[..5].to_vec(),
+        ];
+        for bytes in inputs {
+            assert!(PNGFile::from_bytes(&bytes).is_err());
+        }
+    }
+
+    #[test]
+    fn short_ihdr_is_rejected() {
+        let bytes = png(&[chunk(b"IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 0]), idat(&[0, 0]), iend()]);
+        let (chunk_type, offset, error) = chunk_error(PNGFile::from_bytes(&bytes));
+        assert_eq!((chunk_type.as_str(), offset), ("IHDR", 8));
+        assert!(matches!(error, PNGParseError::ParseError("Invalid IHDR chunk length.")));
+    }
+
+    #[test]
+    fn const_crc_table_matches_the_runtime_one() {
+        let mut table = [0u32; 256];
+        for (n, entry) in table.iter_mut().enumerate() {
+            let mut c = n as u32;
+            for _ in 0..8 {
+                c = if c & 1 != 0 { 0xedb88320 ^ (c >> 1) } else { c >> 1 };
+            }
+            *entry = c;
+        }
+        assert_eq!(CRC_TABLE, table);
+    }
+
+    #[test]
+    fn truncated_zlib_stream_is_an_error() {
+        let scanlines: Vec<u8> = (0..64u32).map(|i| (i * 37 % 251) as u8).collect();
+        let compressed = deflate(&[&[0][..], &scanlines].concat()).unwrap();
+        let bytes = png(&[ihdr(64, 1, 8, 0, 0), chunk(b"IDAT", &compressed[..compressed.len() / 2]), iend()]);
+        let (_, _, error) = chunk_error(PNGFile::from_bytes(&bytes));
+        assert!(matches!(error, PNGParseError::ParseError("Truncated zlib stream in image data.")));
+        let mut corrupt = compressed.clone();
+        *corrupt.last_mut().unwrap() ^= 0xff;
+        let bytes = png(&[ihdr(64, 1, 8, 0, 0), chunk(b"IDAT", &corrupt), iend()]);
+        let (_, _, error) = chunk_error(PNGFile::from_bytes(&bytes));
+        assert!(matches!(error, PNGParseError::ParseError("Invalid zlib stream in image data.")));
+
+        // a stream split over two IDAT chunks is still one stream
+        let (head, tail) = compressed.split_at(5);
+        let bytes = png(&[ihdr(64, 1, 8, 0, 0), chunk(b"IDAT", head), chunk(b"IDAT", tail), iend()]);
+        assert_eq!(PNGFile::from_bytes(&bytes).unwrap().scanlines().unwrap(), [&scanlines[..]]);
+    }
+
+    #[test]
+    fn missing_or_empty_image_data() {
+        let bytes = png(&[ihdr(1, 1, 8, 0, 0), iend()]);
+        let (_, _, error) = chunk_error(PNGFile::from_bytes(&bytes));
+        assert!(matches!(error, PNGParseError::ParseError("No image data before IEND.")));
+        let bytes = png(&[ihdr(1, 1, 8, 0, 0), idat(&[]), iend()]);
+        let (_, _, error) = chunk_error(PNGFile::from_bytes(&bytes));
+        assert!(matches!(error, PNGParseError::ParseError("Image data inflates to nothing.")));
+    }
+
+    #[test]
+    fn ignore_crc_accepts_corrupt_ancillary_chunks() {
+        let bytes = png(&[ihdr(1, 1, 8, 0, 0), corrupt(chunk(b"gAMA", &45455u32.to_be_bytes())), idat(&[0, 0]), iend()]);
+        let checked = PNGFile::from_bytes(&bytes).unwrap();
+        assert_eq!((checked.crc_errors().len(), checked.gamma()), (1, None));
+        for parallel_crc in [false, true] {
+            let unchecked = Decoder::new().ignore_crc(true).parallel_crc(parallel_crc).decode(&bytes[..]).unwrap();
+            assert_eq!((unchecked.crc_errors().len(), unchecked.gamma()), (0, Some(0.45455)));
+        }
+    }
+
+    #[test]
+    fn palette_histogram_from_hist() {
+        let palette = plte(&[[0, 0, 0], [1, 1, 1], [2, 2, 2], [3, 3, 3]]);
+        let histogram = chunk(b"hIST", &[0, 1, 0, 2, 1, 0, 0, 0]);
+        let bytes = png(&[ihdr(1, 1, 8, 3, 0), palette.clone(), histogram, idat(&[0, 0]), iend()]);
+        assert_eq!(PNGFile::from_bytes(&bytes).unwrap().palette_histogram(), Some(&[1, 2, 256, 0][..]));
+
+        let short = chunk(b"hIST", &[0, 1, 0, 2]);
+        let bytes = png(&[ihdr(1, 1, 8, 3, 0), palette, short, idat(&[0, 0]), iend()]);
+        let (_, _, error) = chunk_error(PNGFile::from_bytes(&bytes));
+        assert!(matches!(error, PNGParseError::ParseError("Invalid hIST chunk length.")));
+    }
+
+    #[test]
+    fn from_rgba8_round_trip() {
+        let pixels = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12];
+        let image = PNGFile::from_rgba8(3, 1, pixels.clone()).unwrap();
+        let mut written = vec![];
+        image.write(&mut written).unwrap();
+        let reread = PNGFile::from_bytes(&written).unwrap();
+        assert_eq!((reread.dimensions(), reread.color_type()), ((3, 1), ColorType::Rgba));
+        assert_eq!(reread.into_rgba8().unwrap(), pixels);
+        assert!(matches!(
+            PNGFile::from_rgba8(2, 2, pixels),
+            Err(PNGParseError::ParseError("Pixel data doesn't match the image dimensions."))
+        ));
+    }
+
+    #[test]
+    fn callback_sees_every_chunk() {
+        let bytes = png(&[ihdr(1, 1, 8, 0, 0), chunk(b"tEXt", b"a\0b"), idat(&[0, 0]), chunk(b"IDAT", &[]), iend()]);
+        let mut types = vec![];
+        Decoder::new().decode_with_callback(&bytes[..], |header| types.push(header.type_)).unwrap();
+        assert_eq!(types, [*b"IHDR", *b"tEXt", *b"IDAT", *b"IDAT", *b"IEND"]);
+    }
+
+    #[test]
+    fn palette_index_out_of_range() {
+        let bytes = png(&[ihdr(2, 1, 8, 3, 0), plte(&[[1, 1, 1], [2, 2, 2]]), idat(&[0, 1, 5]), iend()]);
+        let (chunk_type, _, error) = chunk_error(PNGFile::from_bytes(&bytes));
+        assert_eq!(chunk_type, "IDAT");
+        assert!(matches!(error, PNGParseError::ParseError("Palette index out of range.")));
+        let clamped = Decoder::new().clamp_palette_indices(true).decode(&bytes[..]).unwrap();
+        assert_eq!(clamped.into_rgba8().unwrap(), [2, 2, 2, 255, 2, 2, 2, 255]);
+    }
+
+    #[test]
+    fn decode_rows_clamps_palette_indices_like_into_rgba8() {
+        // the Up filter adds the first row to the second, giving index 7
+        let scanlines = [0, 1, 0, 2, 6, 0];
+        let bytes = png(&[ihdr(2, 2, 8, 3, 0), plte(&[[1, 1, 1], [2, 2, 2]]), idat(&scanlines), iend()]);
+        let clamped = Decoder::new().clamp_palette_indices(true).decode(&bytes[..]).unwrap();
+        let rgba = clamped.into_rgba8().unwrap();
+        assert_eq!(rgba, [2, 2, 2, 255, 1, 1, 1, 255, 2, 2, 2, 255, 1, 1, 1, 255]);
+        assert_eq!(clamped.decode_rows(0, 2).unwrap(), rgba);
+        assert_eq!(clamped.decode_rows(1, 2).unwrap(), rgba[8..]);
+    }
+
+    #[test]
+    fn palette_requirements() {
+        let bytes = png(&[ihdr(1, 1, 8, 3, 0), idat(&[0, 0]), iend()]);
+        assert!(matches!(
+            PNGFile::from_bytes(&bytes),
+            Err(PNGParseError::ParseError("Missing PLTE chunk for indexed image."))
+        ));
+        for color_type in [0, 4] {
+            let bytes = png(&[ihdr(1, 1, 8, color_type, 0), plte(&[[0, 0, 0]]), idat(&[0, 0, 0]), iend()]);
+            let (chunk_type, _, error) = chunk_error(PNGFile::from_bytes(&bytes));
+            assert_eq!(chunk_type, "PLTE");
+            assert!(matches!(error, PNGParseError::ParseError("PLTE chunk in a grayscale image.")));
+        }
+        // a suggested palette for truecolor images is fine
+        let bytes = png(&[ihdr(1, 1, 8, 2, 0), plte(&[[0, 0, 0]]), idat(&[0, 0, 0, 0]), iend()]);
+        assert!(PNGFile::from_bytes(&bytes).is_ok());
+    }
+
+    #[test]
+    fn too_many_chunks() {
+        let mut chunks = vec![ihdr(1, 1, 8, 0, 0)];
+        chunks.extend((0..20).map(|_| chunk(b"prIv", &[])));
+        chunks.push(idat(&[0, 0]));
+        chunks.push(iend());
+        let bytes = png(&chunks);
+        for parallel_crc in [false, true] {
+            let decoder = Decoder::new().parallel_crc(parallel_crc);
+            assert!(decoder.clone().max_chunks(22).decode(&bytes[..]).is_ok());
+            assert!(matches!(
+                decoder.max_chunks(21).decode(&bytes[..]),
+                Err(PNGParseError::ParseError("Too many chunks."))
+            ));
+        }
+        // image data chunks don't count
+        let mut chunks = vec![ihdr(1, 1, 8, 0, 0)];
+        chunks.extend((0..20).map(|_| chunk(b"IDAT", &[])));
+        chunks.extend([idat(&[0, 0]), iend()]);
+        assert!(Decoder::new().max_chunks(2).decode(&png(&chunks)[..]).is_ok());
+    }
+
+    #[test]
+    fn io_error_is_the_source() {
+        let error = PNGFile::open(std::env::temp_dir()).unwrap_err();
+        let source = std::error::Error::source(&error)
+            .and_then(|source| source.downcast_ref::<std::io::Error>())
+            .unwrap();
+        assert_eq!(source.kind(), std::io::ErrorKind::IsADirectory);
+    }
+
+    #[test]
+    fn ppm_and_pam_output() {
+        let mut ppm = vec![];
+        PNGFile::from_bytes(&rgb_2x2()).unwrap().write_ppm(&mut ppm).unwrap();
+        assert!(ppm.starts_with(b"P6\n2 2\n255\n"));
+        assert_eq!(ppm[11..17], [255, 0, 0, 0, 255, 0]);
+        assert_eq!(ppm.len(), 11 + 2 * 2 * 3);
+
+        let mut pam = vec![];
+        PNGFile::from_rgba8(1, 1, vec![1, 2, 3, 4]).unwrap().write_ppm(&mut pam).unwrap();
+        let header = b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 4\nMAXVAL 255\nTUPLTYPE RGB_ALPHA\nENDHDR\n";
+        assert_eq!(pam, [&header[..], &[1, 2, 3, 4]].concat());
+    }
+
+    #[test]
+    fn pixel_count_and_byte_size_do_not_overflow() {
+        let side = u16::MAX as u32;
+        let header = PNGFile::read_info(&png(&[ihdr(side, side, 8, 6, 0)])[..]).unwrap();
+        assert_eq!(header.pixel_count(), 4_294_836_225);
+        assert_eq!(header.byte_size(), 4_294_836_225 * 4);
+    }
+
+    #[test]
+    fn huge_dimensions_are_rejected() {
+        let bytes = png(&[ihdr(u32::MAX, u32::MAX, 16, 6, 0), idat(&[0]), iend()]);
+        let (chunk_type, _, error) = chunk_error(PNGFile::from_bytes(&bytes));
+        assert_eq!(chunk_type, "IHDR");
+        assert!(matches!(error, PNGParseError::ParseError("Image dimensions are too large.")));
+        assert!(PNGFile::read_info(&bytes[..]).is_err());
+    }
+
+    #[test]
+    fn progress_reaches_the_file_size() {
+        let bytes = png(&[ihdr(1, 1, 8, 0, 0), chunk(b"tEXt", b"a\0b"), idat(&[0, 0]), iend()]);
+        let total = bytes.len() as u64;
+        let mut reports = vec![];
+        Decoder::new()
+            .decode_with_progress(&bytes[..], total, |read, total| reports.push((read, total)))
+            .unwrap();
+        assert_eq!(reports.len(), 4);
+        assert!(reports.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert_eq!(reports.last(), Some(&(total, total)));
+    }
+
+    #[test]
+    fn repair_crcs_after_editing_data() {
+        let bytes = png(&[ihdr(1, 1, 8, 0, 0), chunk(b"prIv", b"old"), idat(&[0, 0]), iend()]);
+        let mut image = PNGFile::from_bytes(&bytes).unwrap();
+        image.chunks_mut()[0].data_mut().extend_from_slice(b" and new");
+        assert!(!image.chunks()[0].crc_is_valid());
+        image.repair_crcs();
+        let repaired = &image.chunks()[0];
+        assert!(repaired.crc_is_valid());
+        assert_eq!(repaired.size(), 11);
+        assert_eq!(repaired.crc(), crc(b"prIvold and new"));
+    }
+
+    #[cfg(feature = "image")]
+    #[test]
+    fn converts_to_an_image_crate_buffer() {
+        let bytes = png(&[ihdr(2, 1, 8, 4, 0), idat(&[0, 10, 255, 200, 128]), iend()]);
+        let file = PNGFile::from_bytes(&bytes).unwrap();
+        let rgba = file.into_rgba8().unwrap();
+        let converted = image::DynamicImage::try_from(file).unwrap();
+        assert_eq!((converted.width(), converted.height()), (2, 1));
+        assert_eq!(converted.into_rgba8().into_raw(), rgba);
+        assert_eq!(rgba, [10, 10, 10, 255, 200, 200, 200, 128]);
+
+        let empty = PNGFile::with_rgba8(1, 1, vec![]);
+        assert!(matches!(
+            image::DynamicImage::try_from(empty),
+            Err(PNGParseError::ParseError("No decoded image data."))
+        ));
+    }
+
+    #[test]
+    fn metadata_decompression_bombs_are_refused() {
+        let zeros = deflate(&vec![0; 9 << 20]).unwrap();
+        let ztxt = chunk(b"zTXt", &[&b"Comment\0\0"[..], &zeros].concat());
+        let bytes = png(&[ihdr(1, 1, 8, 0, 0), ztxt, idat(&[0, 0]), iend()]);
+        let (chunk_type, offset, error) = chunk_error(PNGFile::from_bytes(&bytes));
+        assert_eq!((chunk_type.as_str(), offset), ("zTXt", 33));
+        assert!(matches!(error, PNGParseError::ParseError("Chunk data exceeds the metadata size limit.")));
+        let decoded = Decoder::new().max_metadata_size(9 << 20).decode(&bytes[..]).unwrap();
+        assert_eq!(decoded.text_entries()[0].text.len(), 9 << 20);
+
+        let profile = deflate(&[7; 2000]).unwrap();
+        let iccp = chunk(b"iCCP", &[&b"sRGB\0\0"[..], &profile].concat());
+        let bytes = png(&[ihdr(1, 1, 8, 0, 0), iccp, idat(&[0, 0]), iend()]);
+        let (chunk_type, _, error) = chunk_error(Decoder::new().max_metadata_size(1999).decode(&bytes[..]));
+        assert_eq!(chunk_type, "iCCP");
+        assert!(matches!(error, PNGParseError::ParseError("Chunk data exceeds the metadata size limit.")));
+        let decoded = Decoder::new().max_metadata_size(2000).decode(&bytes[..]).unwrap();
+        assert_eq!(decoded.icc_profile(), Some(vec![7; 2000]));
+
+        let itxt = chunk(b"iTXt", &[&b"Comment\0\x01\0\0\0"[..], &profile].concat());
+        let bytes = png(&[ihdr(1, 1, 8, 0, 0), itxt, idat(&[0, 0]), iend()]);
+        assert!(Decoder::new().max_metadata_size(1999).decode(&bytes[..]).is_err());
+    }
 }
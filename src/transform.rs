@@ -0,0 +1,354 @@
+use crate::png::{ColorType, PNGFile, PNGParseError};
+
+impl PNGFile {
+    /// Copy the `w` x `h` rectangle whose top left corner is at (x, y) into
+    /// a new 8-bit RGBA image.
+    pub fn crop(&self, x: u32, y: u32, w: u32, h: u32) -> Result<PNGFile, PNGParseError> {
+        let fits = |start: u32, len: u32, max: u32| start.checked_add(len).is_some_and(|end| end <= max);
+        if w == 0 || h == 0 || !fits(x, w, self.width()) || !fits(y, h, self.height()) {
+            return Err(PNGParseError::ParseError("Crop rectangle is out of bounds."));
+        }
+        let mut pixels: Vec<u8> = Vec::with_capacity(w as usize * h as usize * 4);
+        for row in y..y + h {
+            for col in x..x + w {
+                let pixel = self
+                    .get_pixel(col, row)
+                    .ok_or(PNGParseError::ParseError("Invalid pixel data."))?;
+                pixels.extend_from_slice(&pixel);
+            }
+        }
+        Ok(PNGFile::with_rgba8(w, h, pixels))
+    }
+
+    /// Mirror the image left to right, producing an 8-bit RGBA image.
+    pub fn flip_horizontal(&self) -> PNGFile {
+        let mut pixels = self.rgba8_pixels();
+        for row in pixels.chunks_mut(self.width().max(1) as usize) {
+            row.reverse();
+        }
+        PNGFile::with_rgba8(self.width(), self.height(), pixels.into_iter().flatten().collect())
+    }
+
+    /// Mirror the image top to bottom, producing an 8-bit RGBA image.
+    pub fn flip_vertical(&self) -> PNGFile {
+        let pixels = self.rgba8_pixels();
+        let flipped = pixels
+            .chunks(self.width().max(1) as usize)
+            .rev()
+            .flatten()
+            .flatten()
+            .copied()
+            .collect();
+        PNGFile::with_rgba8(self.width(), self.height(), flipped)
+    }
+
+    /// Rescale the image to `new_w` x `new_h` with nearest-neighbor
+    /// sampling, producing an 8-bit RGBA image.
+    pub fn resize_nearest(&self, new_w: u32, new_h: u32) -> Result<PNGFile, PNGParseError> {
+        if new_w == 0 || new_h == 0 || self.width() == 0 || self.height() == 0 {
+            return Err(PNGParseError::ParseError("Invalid resize dimensions."));
+        }
+        PNGFile::with_rgba8(new_w, new_h, vec![]).validate_header()?;
+        let source = self.rgba8_pixels();
+        let mut pixels: Vec<u8> = Vec::with_capacity(new_w as usize * new_h as usize * 4);
+        for y in 0..new_h as u64 {
+            let source_y = y * self.height() as u64 / new_h as u64;
+            for x in 0..new_w as u64 {
+                let source_x = x * self.width() as u64 / new_w as u64;
+                let index = source_y * self.width() as u64 + source_x;
+                let pixel = source
+                    .get(index as usize)
+                    .ok_or(PNGParseError::ParseError("No decoded image data."))?;
+                pixels.extend_from_slice(pixel);
+            }
+        }
+        Ok(PNGFile::with_rgba8(new_w, new_h, pixels))
+    }
+
+    /// Rescale the image to `new_w` x `new_h` by averaging the source pixels
+    /// each destination pixel's area covers, weighted by how much of them it
+    /// covers, producing an 8-bit RGBA image. Smoother than `resize_nearest`
+    /// at any scale ratio. Colors are weighted by alpha so transparent pixels
+    /// don't bleed into their neighbors.
+    pub fn resize_box(&self, new_w: u32, new_h: u32) -> Result<PNGFile, PNGParseError> {
+        if new_w == 0 || new_h == 0 || self.width() == 0 || self.height() == 0 {
+            return Err(PNGParseError::ParseError("Invalid resize dimensions."));
+        }
+        PNGFile::with_rgba8(new_w, new_h, vec![]).validate_header()?;
+        let source = self.rgba8_pixels();
+        if source.len() as u64 != self.pixel_count() {
+            return Err(PNGParseError::ParseError("No decoded image data."));
+        }
+        let columns = box_weights(self.width(), new_w);
+        let rows = box_weights(self.height(), new_h);
+        let mut pixels: Vec<u8> = Vec::with_capacity(new_w as usize * new_h as usize * 4);
+        for row in &rows {
+            for column in &columns {
+                // alpha-weighted color sums and the average alpha
+                let mut sums = [0f64; 4];
+                for &(y, y_weight) in row {
+                    for &(x, x_weight) in column {
+                        let [r, g, b, a] = source[y * self.width() as usize + x];
+                        let alpha = a as f64 * y_weight * x_weight;
+                        for (sum, channel) in sums.iter_mut().zip([r, g, b]) {
+                            *sum += channel as f64 * alpha;
+                        }
+                        sums[3] += alpha;
+                    }
+                }
+                if sums[3] == 0.0 {
+                    pixels.extend_from_slice(&[0, 0, 0, 0]);
+                } else {
+                    pixels.extend(sums[..3].iter().map(|sum| (sum / sums[3]).round() as u8));
+                    pixels.push(sums[3].round() as u8);
+                }
+            }
+        }
+        Ok(PNGFile::with_rgba8(new_w, new_h, pixels))
+    }
+
+    /// Convert the image to 8-bit grayscale using the luma weights
+    /// 0.2126 R + 0.7152 G + 0.0722 B. Images with an alpha channel or a
+    /// tRNS chunk become grayscale with alpha (color type 4), the others
+    /// plain grayscale (color type 0).
+    pub fn to_grayscale(&self) -> PNGFile {
+        let keep_alpha = self.has_alpha();
+        let mut samples: Vec<u8> = Vec::new();
+        for [r, g, b, a] in self.rgba8_pixels() {
+            let luma = 0.2126 * r as f64 + 0.7152 * g as f64 + 0.0722 * b as f64;
+            samples.push(luma.round() as u8);
+            if keep_alpha {
+                samples.push(a);
+            }
+        }
+        let color_type = if keep_alpha { ColorType::GrayscaleAlpha } else { ColorType::Grayscale };
+        PNGFile::with_image_data(self.width(), self.height(), color_type, 8, samples)
+    }
+
+    /// Convert the image to `color_type` at `bit_depth`, e.g. RGBA8 to 8-bit
+    /// grayscale or RGB8 to 16-bit RGBA. Samples are rescaled to the new
+    /// depth and gray is computed with the luma weights of `to_grayscale`.
+    /// Converting to indexed color isn't supported, and neither is dropping
+    /// the alpha of an image with transparent pixels.
+    pub fn convert(&self, color_type: ColorType, bit_depth: u8) -> Result<PNGFile, PNGParseError> {
+        if color_type == ColorType::Palette {
+            return Err(PNGParseError::ParseError("Conversion to indexed color is not supported."));
+        }
+        PNGFile::with_image_data(self.width(), self.height(), color_type, bit_depth, vec![]).validate_header()?;
+        let keep_alpha = matches!(color_type, ColorType::GrayscaleAlpha | ColorType::Rgba);
+        let max = (1u32 << bit_depth) - 1;
+        let mut samples: Vec<u8> = Vec::new();
+        let mut push = |value: u16| {
+            if bit_depth == 16 {
+                samples.extend_from_slice(&value.to_be_bytes());
+            } else {
+                samples.push(((value as u32 * max + 32767) / 65535) as u8);
+            }
+        };
+        for y in 0..self.height() {
+            for x in 0..self.width() {
+                let [r, g, b, a] = self
+                    .get_pixel16(x, y)
+                    .ok_or(PNGParseError::ParseError("Invalid pixel data."))?;
+                if !keep_alpha && a != u16::MAX {
+                    return Err(PNGParseError::ParseError(
+                        "Dropping the alpha channel would lose transparency.",
+                    ));
+                }
+                match color_type {
+                    ColorType::Grayscale | ColorType::GrayscaleAlpha => {
+                        let luma = 0.2126 * r as f64 + 0.7152 * g as f64 + 0.0722 * b as f64;
+                        push(luma.round() as u16);
+                    }
+                    _ => [r, g, b].into_iter().for_each(&mut push),
+                }
+                if keep_alpha {
+                    push(a);
+                }
+            }
+        }
+        Ok(PNGFile::with_image_data(self.width(), self.height(), color_type, bit_depth, samples))
+    }
+
+    /// Composite the image over the solid color `bg`, producing an opaque
+    /// 8-bit RGB image, e.g. before exporting to a format without alpha.
+    /// Each channel becomes `(c * a + bg * (255 - a)) / 255`, rounded.
+    pub fn flatten(&self, bg: [u8; 3]) -> PNGFile {
+        let mut samples: Vec<u8> = Vec::with_capacity(self.pixel_count() as usize * 3);
+        for [r, g, b, a] in self.rgba8_pixels() {
+            let alpha = a as u32;
+            for (channel, background) in [r, g, b].into_iter().zip(bg) {
+                let blended = channel as u32 * alpha + background as u32 * (255 - alpha);
+                samples.push(((blended + 127) / 255) as u8);
+            }
+        }
+        PNGFile::with_image_data(self.width(), self.height(), ColorType::Rgb, 8, samples)
+    }
+
+    /// Count how often each value occurs in the R, G, B and A channels of
+    /// the pixels expanded to RGBA8, indexed as `histogram[channel][value]`.
+    pub fn histogram(&self) -> [[u32; 256]; 4] {
+        let mut histogram = [[0u32; 256]; 4];
+        for (_, _, pixel) in self.pixels() {
+            for (channel, value) in pixel.iter().enumerate() {
+                histogram[channel][*value as usize] += 1;
+            }
+        }
+        histogram
+    }
+
+    /// All pixels expanded to RGBA8 in row-major order, pixels that can't be
+    /// expanded come out as transparent black like in `pixels`.
+    fn rgba8_pixels(&self) -> Vec<[u8; 4]> {
+        self.pixels().map(|(_, _, pixel)| pixel).collect()
+    }
+}
+
+/// For each of `dst_len` destination pixels along one axis, the source
+/// pixels it covers and the fraction of its area each of them makes up.
+fn box_weights(src_len: u32, dst_len: u32) -> Vec<Vec<(usize, f64)>> {
+    let (src_len, dst_len) = (src_len as u64, dst_len as u64);
+    (0..dst_len)
+        .map(|dst| {
+            // measured in 1/dst_len of a source pixel, the destination pixel
+            // spans dst*src_len..(dst+1)*src_len and source pixel s spans
+            // s*dst_len..(s+1)*dst_len
+            let start = dst * src_len;
+            let end = start + src_len;
+            (start / dst_len..end.div_ceil(dst_len))
+                .map(|src| {
+                    let overlap = end.min((src + 1) * dst_len) - start.max(src * dst_len);
+                    (src as usize, overlap as f64 / src_len as f64)
+                })
+                .collect()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rgba(width: u32, height: u32, pixels: &[[u8; 4]]) -> PNGFile {
+        PNGFile::from_rgba8(width, height, pixels.concat()).unwrap()
+    }
+
+    /// A 3x2 image whose pixels are numbered 0 to 5 in row-major order.
+    fn numbered_3x2() -> PNGFile {
+        let pixels: Vec<[u8; 4]> = (0..6).map(|i| [i, i, i, 255]).collect();
+        rgba(3, 2, &pixels)
+    }
+
+    #[test]
+    fn crop_copies_the_rectangle() {
+        let cropped = numbered_3x2().crop(1, 0, 2, 2).unwrap();
+        assert_eq!(cropped.dimensions(), (2, 2));
+        let numbers: Vec<u8> = cropped.rgba8_pixels().iter().map(|pixel| pixel[0]).collect();
+        assert_eq!(numbers, [1, 2, 4, 5]);
+        let whole = numbered_3x2().crop(0, 0, 3, 2).unwrap();
+        assert_eq!(whole.into_rgba8().unwrap(), numbered_3x2().into_rgba8().unwrap());
+    }
+
+    #[test]
+    fn crop_outside_the_image_is_an_error() {
+        for (x, y, w, h) in [(2, 0, 2, 1), (0, 1, 1, 2), (0, 0, 0, 1), (u32::MAX, 0, 2, 1)] {
+            assert!(matches!(
+                numbered_3x2().crop(x, y, w, h),
+                Err(PNGParseError::ParseError("Crop rectangle is out of bounds."))
+            ));
+        }
+    }
+
+    #[test]
+    fn flips_swap_the_corners() {
+        let numbers = |image: &PNGFile| -> Vec<u8> { image.rgba8_pixels().iter().map(|pixel| pixel[0]).collect() };
+        assert_eq!(numbers(&numbered_3x2().flip_horizontal()), [2, 1, 0, 5, 4, 3]);
+        assert_eq!(numbers(&numbered_3x2().flip_vertical()), [3, 4, 5, 0, 1, 2]);
+        let twice = numbered_3x2().flip_horizontal().flip_vertical();
+        assert_eq!((twice.get_pixel(0, 0), twice.get_pixel(2, 1)), (Some([5, 5, 5, 255]), Some([0, 0, 0, 255])));
+    }
+
+    #[test]
+    fn resize_nearest_replicates_pixels() {
+        let (a, b, c, d) = ([1, 1, 1, 255], [2, 2, 2, 255], [3, 3, 3, 255], [4, 4, 4, 255]);
+        let resized = rgba(2, 2, &[a, b, c, d]).resize_nearest(4, 4).unwrap();
+        let expected = [[a, a, b, b], [a, a, b, b], [c, c, d, d], [c, c, d, d]].concat();
+        assert_eq!(resized.rgba8_pixels(), expected);
+        assert!(matches!(
+            rgba(2, 2, &[a, b, c, d]).resize_nearest(0, 4),
+            Err(PNGParseError::ParseError("Invalid resize dimensions."))
+        ));
+    }
+
+    #[test]
+    fn to_grayscale_uses_the_luma_weights() {
+        let opaque = rgba(3, 1, &[[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255]]).convert(ColorType::Rgb, 8).unwrap();
+        let gray = opaque.to_grayscale();
+        assert_eq!((gray.color_type(), gray.bit_depth()), (ColorType::Grayscale, 8));
+        assert_eq!(gray.scanlines().unwrap(), [&[54, 182, 18][..]]);
+
+        let translucent = rgba(1, 1, &[[255, 0, 0, 100]]).to_grayscale();
+        assert_eq!(translucent.color_type(), ColorType::GrayscaleAlpha);
+        assert_eq!(translucent.scanlines().unwrap(), [&[54, 100][..]]);
+    }
+
+    #[test]
+    fn histogram_counts_each_channel() {
+        let image = rgba(2, 2, &[[0, 10, 20, 255], [0, 10, 30, 255], [5, 10, 20, 0], [0, 0, 0, 255]]);
+        let histogram = image.histogram();
+        assert_eq!((histogram[0][0], histogram[0][5]), (3, 1));
+        assert_eq!((histogram[1][10], histogram[1][0]), (3, 1));
+        assert_eq!((histogram[2][20], histogram[2][30], histogram[2][0]), (2, 1, 1));
+        assert_eq!((histogram[3][255], histogram[3][0]), (3, 1));
+        assert!(histogram.iter().all(|channel| channel.iter().sum::<u32>() == 4));
+    }
+
+    #[test]
+    fn conversions_between_pixel_formats() {
+        let image = rgba(2, 1, &[[255, 0, 0, 255], [0, 0, 255, 255]]);
+        let gray = image.convert(ColorType::Grayscale, 8).unwrap();
+        assert_eq!(gray.scanlines().unwrap(), [&[54, 18][..]]);
+        let rgba16 = image.convert(ColorType::Rgb, 8).unwrap().convert(ColorType::Rgba, 16).unwrap();
+        assert_eq!(rgba16.get_pixel16(1, 0), Some([0, 0, 0xFFFF, 0xFFFF]));
+        assert_eq!(rgba16.scanlines().unwrap()[0].len(), 2 * 8);
+        let bits = rgba(2, 1, &[[255, 255, 255, 255], [0, 0, 0, 255]]).convert(ColorType::Grayscale, 1).unwrap();
+        assert_eq!(bits.scanlines().unwrap(), [&[1, 0][..]]);
+        let gray_alpha = rgba(1, 1, &[[0, 255, 0, 100]]).convert(ColorType::GrayscaleAlpha, 8).unwrap();
+        assert_eq!(gray_alpha.get_pixel(0, 0), Some([182, 182, 182, 100]));
+
+        let translucent = rgba(1, 1, &[[0, 0, 0, 100]]);
+        assert!(matches!(
+            translucent.convert(ColorType::Rgb, 8),
+            Err(PNGParseError::ParseError("Dropping the alpha channel would lose transparency."))
+        ));
+        assert!(matches!(
+            translucent.convert(ColorType::Palette, 8),
+            Err(PNGParseError::ParseError("Conversion to indexed color is not supported."))
+        ));
+        assert!(matches!(
+            translucent.convert(ColorType::Rgba, 4),
+            Err(PNGParseError::ParseError("Invalid bit depth for color type."))
+        ));
+    }
+
+    #[test]
+    fn flatten_blends_over_the_background() {
+        let flat = rgba(2, 1, &[[255, 255, 255, 128], [10, 20, 30, 255]]).flatten([0, 0, 0]);
+        assert_eq!((flat.color_type(), flat.bit_depth()), (ColorType::Rgb, 8));
+        assert_eq!(flat.scanlines().unwrap(), [&[128, 128, 128, 10, 20, 30][..]]);
+        let over_red = rgba(1, 1, &[[0, 0, 0, 0]]).flatten([255, 0, 0]);
+        assert_eq!(over_red.get_pixel(0, 0), Some([255, 0, 0, 255]));
+    }
+
+    #[test]
+    fn resize_box_averages_a_checkerboard() {
+        let (black, white) = ([0, 0, 0, 255], [255, 255, 255, 255]);
+        let pixels: Vec<[u8; 4]> = (0..16).map(|i| if (i % 4 + i / 4) % 2 == 0 { black } else { white }).collect();
+        let resized = rgba(4, 4, &pixels).resize_box(2, 2).unwrap();
+        assert_eq!(resized.rgba8_pixels(), [[128, 128, 128, 255]; 4]);
+
+        // three source pixels per two destination pixels
+        let resized = rgba(3, 1, &[black, white, black]).resize_box(2, 1).unwrap();
+        assert_eq!(resized.rgba8_pixels(), [[85, 85, 85, 255]; 2]);
+    }
+}
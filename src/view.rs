@@ -0,0 +1,174 @@
+//! Zero-copy access to the chunks of a PNG held in memory, e.g. a
+//! memory-mapped file.
+
+use std::borrow::Cow;
+
+use crate::png::{Crc32, PNGFile, PNGParseError, MAX_CHUNK_LEN, PNG_SIGNATURE};
+
+/// A chunk borrowing its data from the underlying buffer.
+#[derive(Clone, Copy)]
+pub struct ChunkRef<'a> {
+    type_: [u8; 4],
+    data: &'a [u8],
+    crc: u32,
+}
+
+impl<'a> ChunkRef<'a> {
+    /// The four chunk type bytes, e.g. `b"IHDR"`.
+    pub fn chunk_type(&self) -> &[u8; 4] {
+        &self.type_
+    }
+
+    /// The chunk type as text for display, see `Chunk::type_str`.
+    pub fn type_str(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.type_)
+    }
+
+    /// The chunk data, a slice of the viewed buffer.
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    pub fn crc(&self) -> u32 {
+        self.crc
+    }
+
+    pub fn crc_is_valid(&self) -> bool {
+        let mut crc = Crc32::new();
+        crc.update(&self.type_);
+        crc.update(self.data);
+        crc.finalize() == self.crc
+    }
+}
+
+/// The chunk layout of a PNG buffer, without decoding it or copying any
+/// chunk data.
+pub struct PNGView<'a> {
+    bytes: &'a [u8],
+    chunks: Vec<ChunkRef<'a>>,
+}
+
+impl<'a> PNGView<'a> {
+    /// Split `bytes` into chunks, up to and including IEND.
+    pub fn new(bytes: &'a [u8]) -> Result<PNGView<'a>, PNGParseError> {
+        let mut rest = bytes
+            .strip_prefix(&PNG_SIGNATURE[..])
+            .ok_or(PNGParseError::ParseError("Invalid png file, wrong signiture."))?;
+        let mut chunks = vec![];
+        while !rest.is_empty() {
+            if rest.len() < 12 {
+                return Err(PNGParseError::EOF);
+            }
+            let size = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]);
+            if size > MAX_CHUNK_LEN {
+                return Err(PNGParseError::ParseError("Chunk length exceeds 2^31-1 bytes."));
+            }
+            let size = size as usize;
+            let type_ = [rest[4], rest[5], rest[6], rest[7]];
+            if rest.len() - 12 < size {
+                return Err(PNGParseError::EOF);
+            }
+            let data = &rest[8..8 + size];
+            let crc_bytes = &rest[8 + size..12 + size];
+            chunks.push(ChunkRef {
+                type_,
+                data,
+                crc: u32::from_be_bytes([crc_bytes[0], crc_bytes[1], crc_bytes[2], crc_bytes[3]]),
+            });
+            rest = &rest[12 + size..];
+            if &type_ == b"IEND" {
+                break;
+            }
+        }
+        Ok(PNGView { bytes, chunks })
+    }
+
+    /// All chunks in file order.
+    pub fn chunks(&self) -> &[ChunkRef<'a>] {
+        &self.chunks
+    }
+
+    /// Chunks of the given type in file order.
+    pub fn chunks_of_type(&self, type_: &[u8; 4]) -> impl Iterator<Item = &ChunkRef<'a>> + '_ {
+        let type_ = *type_;
+        self.chunks.iter().filter(move |chunk| chunk.type_ == type_)
+    }
+
+    /// The compressed image data. Borrowed when the file has a single IDAT
+    /// chunk, only several IDAT chunks need to be concatenated.
+    pub fn idat_data(&self) -> Cow<'a, [u8]> {
+        let mut idat = self.chunks.iter().filter(|chunk| &chunk.type_ == b"IDAT");
+        match (idat.next(), idat.next()) {
+            (None, _) => Cow::Borrowed(&[]),
+            (Some(chunk), None) => Cow::Borrowed(chunk.data),
+            (Some(_), Some(_)) => Cow::Owned(
+                self.chunks_of_type(b"IDAT")
+                    .flat_map(|chunk| chunk.data.iter().copied())
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Fully decode the viewed buffer.
+    pub fn decode(&self) -> Result<PNGFile, PNGParseError> {
+        PNGFile::from_bytes(self.bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(type_: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut crc = Crc32::new();
+        crc.update(type_);
+        crc.update(data);
+        let mut bytes = (data.len() as u32).to_be_bytes().to_vec();
+        bytes.extend_from_slice(type_);
+        bytes.extend_from_slice(data);
+        bytes.extend_from_slice(&crc.finalize().to_be_bytes());
+        bytes
+    }
+
+    fn png(chunks: &[Vec<u8>]) -> Vec<u8> {
+        [&PNG_SIGNATURE[..], &chunks.concat()].concat()
+    }
+
+    #[test]
+    fn chunk_data_borrows_from_the_buffer() {
+        let bytes = png(&[
+            chunk(b"IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0]),
+            chunk(b"tEXt", b"Title\0ruro"),
+            chunk(b"IDAT", &[1, 2]),
+            chunk(b"IDAT", &[3]),
+            chunk(b"IEND", &[]),
+        ]);
+        let view = PNGView::new(&bytes).unwrap();
+        let range = bytes.as_ptr_range();
+        for chunk in view.chunks() {
+            let data = chunk.data().as_ptr_range();
+            assert!(range.start <= data.start && data.end <= range.end);
+            assert!(chunk.crc_is_valid());
+        }
+        let types: Vec<&[u8; 4]> = view.chunks().iter().map(|chunk| chunk.chunk_type()).collect();
+        assert_eq!(types, [b"IHDR", b"tEXt", b"IDAT", b"IDAT", b"IEND"]);
+        assert_eq!(view.chunks()[1].data().as_ptr(), bytes[8 + 25 + 8..].as_ptr());
+        assert_eq!(&*view.idat_data(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn oversized_chunk_lengths_are_rejected() {
+        let mut bytes = png(&[chunk(b"IHDR", &[0; 13])]);
+        bytes[8..12].copy_from_slice(&0x8000_0000u32.to_be_bytes());
+        assert!(matches!(
+            PNGView::new(&bytes),
+            Err(PNGParseError::ParseError("Chunk length exceeds 2^31-1 bytes."))
+        ));
+        match PNGFile::from_bytes(&bytes) {
+            Err(PNGParseError::InChunk { source, .. }) => {
+                assert!(matches!(*source, PNGParseError::ParseError("Chunk length exceeds 2^31-1 bytes.")))
+            }
+            other => panic!("unexpected result {:?}", other.map(|_| ())),
+        }
+    }
+}
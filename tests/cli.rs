@@ -0,0 +1,220 @@
+//! Runs the `ruro` binary against the images in benches/fixtures.
+
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+use ruro::png::{ColorType, PNGFile};
+
+fn fixture(name: &str) -> String {
+    format!("{}/benches/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+/// A path in the temporary directory unique to this test process.
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("ruro-cli-{}-{}", std::process::id(), name))
+}
+
+fn ruro(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_ruro")).args(args).output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+/// Just enough of JSON to read back what `inspect --format json` prints.
+#[derive(Debug, PartialEq)]
+enum Json {
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn parse(text: &str) -> Json {
+        let mut chars = text.trim().chars().peekable();
+        let value = Json::parse_value(&mut chars);
+        assert_eq!(chars.next(), None, "trailing characters after the JSON value");
+        value
+    }
+
+    fn parse_value(chars: &mut std::iter::Peekable<std::str::Chars>) -> Json {
+        match chars.next() {
+            Some('{') => {
+                let mut members = vec![];
+                while chars.peek() != Some(&'}') {
+                    let Json::String(key) = Json::parse_value(chars) else {
+                        panic!("object keys must be strings");
+                    };
+                    assert_eq!(chars.next(), Some(':'));
+                    members.push((key, Json::parse_value(chars)));
+                    if chars.peek() == Some(&',') {
+                        chars.next();
+                    }
+                }
+                chars.next();
+                Json::Object(members)
+            }
+            Some('[') => {
+                let mut items = vec![];
+                while chars.peek() != Some(&']') {
+                    items.push(Json::parse_value(chars));
+                    if chars.peek() == Some(&',') {
+                        chars.next();
+                    }
+                }
+                chars.next();
+                Json::Array(items)
+            }
+            Some('"') => {
+                let mut string = String::new();
+                loop {
+                    match chars.next().expect("unterminated string") {
+                        '"' => return Json::String(string),
+                        '\\' => match chars.next() {
+                            Some('u') => {
+                                let code: String = chars.by_ref().take(4).collect();
+                                string.push(char::from_u32(u32::from_str_radix(&code, 16).unwrap()).unwrap());
+                            }
+                            Some(c) => string.push(c),
+                            None => panic!("unterminated escape"),
+                        },
+                        c => string.push(c),
+                    }
+                }
+            }
+            Some(c @ ('t' | 'f')) => {
+                let rest: String = chars.by_ref().take(if c == 't' { 3 } else { 4 }).collect();
+                assert!(rest == "rue" || rest == "alse", "invalid literal");
+                Json::Bool(c == 't')
+            }
+            Some(c) => {
+                let mut number = c.to_string();
+                while let Some(c) = chars.next_if(|c| c.is_ascii_digit() || "+-.eE".contains(*c)) {
+                    number.push(c);
+                }
+                Json::Number(number.parse().expect("invalid number"))
+            }
+            None => panic!("unexpected end of JSON"),
+        }
+    }
+
+    fn get(&self, key: &str) -> &Json {
+        let Json::Object(members) = self else {
+            panic!("not an object: {:?}", self);
+        };
+        &members.iter().find(|(name, _)| name == key).unwrap().1
+    }
+}
+
+#[test]
+fn inspect_prints_json() {
+    let output = ruro(&["inspect", "-i", &fixture("paletted.png"), "-f", "json"]);
+    assert!(output.status.success());
+    let json = Json::parse(&stdout(&output));
+    assert_eq!(json.get("width"), &Json::Number(64.0));
+    assert_eq!(json.get("height"), &Json::Number(64.0));
+    assert_eq!(json.get("bit_depth"), &Json::Number(4.0));
+    assert_eq!(json.get("color_type"), &Json::Number(3.0));
+    assert_eq!(json.get("interlaced"), &Json::Bool(false));
+    let Json::Array(chunks) = json.get("chunks") else {
+        panic!("chunks is not an array");
+    };
+    let types: Vec<&Json> = chunks.iter().map(|chunk| chunk.get("type")).collect();
+    let expected = ["IHDR", "PLTE", "IDAT", "IEND"].map(|type_| Json::String(type_.to_string()));
+    assert_eq!(types, expected.iter().collect::<Vec<_>>());
+    assert!(chunks.iter().all(|chunk| chunk.get("crc_ok") == &Json::Bool(true)));
+    assert_eq!(chunks[0].get("size"), &Json::Number(13.0));
+}
+
+#[test]
+fn inspect_json_of_a_truncated_file_fails() {
+    let bytes = std::fs::read(fixture("paletted.png")).unwrap();
+    let path = temp_path("truncated.png");
+    std::fs::write(&path, &bytes[..100]).unwrap();
+    let output = ruro(&["inspect", "-i", path.to_str().unwrap(), "-f", "json"]);
+    std::fs::remove_file(&path).unwrap();
+    assert!(!output.status.success());
+    assert!(stdout(&output).starts_with("Could not parse"));
+    // the location and the error it wraps, each once
+    assert!(stdout(&output).ends_with(": in `????` chunk at byte 93: End of file error.\n"));
+}
+
+#[test]
+fn inspect_subcommand_describes_the_file() {
+    let output = ruro(&["inspect", "-i", &fixture("interlaced.png")]);
+    assert!(output.status.success());
+    assert!(stdout(&output).starts_with("PNG 256x256 8-bit RGB interlaced"));
+}
+
+#[test]
+fn no_subcommand_still_inspects() {
+    let inspected = ruro(&["inspect", "-i", &fixture("paletted.png")]);
+    let legacy = ruro(&["-i", &fixture("paletted.png")]);
+    assert!(legacy.status.success());
+    assert_eq!(legacy.stdout, inspected.stdout);
+    // the former output flag is still accepted
+    let with_output = ruro(&["-i", &fixture("paletted.png"), "-o", "ignored.png"]);
+    assert!(with_output.status.success());
+    assert_eq!(with_output.stdout, inspected.stdout);
+    let json = ruro(&["-i", &fixture("paletted.png"), "-f", "json"]);
+    assert_eq!(json.stdout, ruro(&["inspect", "-i", &fixture("paletted.png"), "-f", "json"]).stdout);
+}
+
+#[test]
+fn decode_subcommand_writes_the_pixels() {
+    let rgba = PNGFile::open(fixture("paletted.png")).unwrap().into_rgba8().unwrap();
+    let raw = temp_path("decoded.rgba");
+    let output = ruro(&["decode", "-i", &fixture("paletted.png"), "-o", raw.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_eq!(std::fs::read(&raw).unwrap(), rgba);
+    std::fs::remove_file(&raw).unwrap();
+
+    let ppm = temp_path("decoded.ppm");
+    let output = ruro(&["decode", "-i", &fixture("paletted.png"), "-o", ppm.to_str().unwrap(), "-f", "ppm"]);
+    assert!(output.status.success());
+    let written = std::fs::read(&ppm).unwrap();
+    std::fs::remove_file(&ppm).unwrap();
+    let header = b"P6\n64 64\n255\n";
+    assert_eq!(&written[..header.len()], header);
+    let rgb: Vec<u8> = rgba.chunks_exact(4).flat_map(|pixel| &pixel[..3]).copied().collect();
+    assert_eq!(&written[header.len()..], rgb);
+}
+
+#[test]
+fn convert_subcommand_changes_the_color_type() {
+    let path = temp_path("converted.png");
+    let output = ruro(&["convert", "-i", &fixture("paletted.png"), "-o", path.to_str().unwrap(), "-c", "rgb"]);
+    assert!(output.status.success());
+    let converted = PNGFile::open(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!((converted.color_type(), converted.bit_depth()), (ColorType::Rgb, 8));
+    let original = PNGFile::open(fixture("paletted.png")).unwrap();
+    assert_eq!(converted.into_rgba8().unwrap(), original.into_rgba8().unwrap());
+
+    let output = ruro(&["convert", "-i", &fixture("paletted.png"), "-o", path.to_str().unwrap(), "-c", "rgb", "-b", "5"]);
+    assert!(!output.status.success());
+    assert!(stdout(&output).starts_with("Could not convert"));
+    assert!(!path.exists());
+}
+
+#[test]
+fn library_writes_nothing_to_stdout() {
+    // decode and convert print nothing themselves on success, anything on
+    // stdout would come from parsing, decoding or encoding in the library
+    let raw = temp_path("quiet.rgba");
+    let decoded = ruro(&["decode", "-i", &fixture("interlaced.png"), "-o", raw.to_str().unwrap()]);
+    std::fs::remove_file(&raw).unwrap();
+    assert!(decoded.status.success());
+    assert_eq!(stdout(&decoded), "");
+    let png = temp_path("quiet.png");
+    let converted = ruro(&["convert", "-i", &fixture("paletted.png"), "-o", png.to_str().unwrap(), "-c", "gray"]);
+    std::fs::remove_file(&png).unwrap();
+    assert!(converted.status.success());
+    assert_eq!(stdout(&converted), "");
+
+    let inspected = ruro(&["inspect", "-i", &fixture("paletted.png")]);
+    assert_eq!(stdout(&inspected), "PNG 64x64 4-bit indexed, 0 ancillary chunks\nchunks length: 0\n");
+}